@@ -0,0 +1,105 @@
+// Compares executing with the decoded instruction cache, against decoding every instruction.
+//
+//     cargo bench --bench vm
+
+use aoc19::vm::{AmplifierChain, ExecutionOption, Topology, VM};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use itertools::Itertools;
+
+fn load(day: u8) -> Vec<i64> {
+    std::fs::read_to_string(format!("input/2019/day{}.txt", day))
+        .expect("Unable to read input.")
+        .trim()
+        .split(',')
+        .map(|v| v.parse().expect("Unable to parse input."))
+        .collect()
+}
+
+fn vm(program: &[i64], cached: bool) -> VM<i64> {
+    let vm = VM::new(program);
+
+    if cached {
+        vm
+    } else {
+        vm.without_instruction_cache()
+    }
+}
+
+fn variants() -> [(&'static str, bool); 2] {
+    [("decode", false), ("cached", true)]
+}
+
+// a slice of the noun/verb search of day 2
+fn day2(c: &mut Criterion) {
+    let program = load(2);
+    let mut group = c.benchmark_group("day2 search");
+
+    for (name, cached) in variants().iter() {
+        let base = vm(&program, *cached);
+
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                for noun in 0..16 {
+                    for verb in 0..16 {
+                        let mut vm = base.fork();
+                        vm.tape.write(1, noun).unwrap();
+                        vm.tape.write(2, verb).unwrap();
+                        vm.execute(ExecutionOption::default()).unwrap();
+                    }
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+// the thermal radiator diagnostics of day 5
+fn day5(c: &mut Criterion) {
+    let program = load(5);
+    let mut group = c.benchmark_group("day5 diagnostics");
+
+    for (name, cached) in variants().iter() {
+        let base = vm(&program, *cached);
+
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                let mut vm = base.fork();
+                vm.push_input(5);
+                vm.execute(ExecutionOption::OutputByTapeOutput).unwrap()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+// all phase setting permutations of the day 7 feedback loop
+fn day7(c: &mut Criterion) {
+    let program = load(7);
+    let mut group = c.benchmark_group("day7 feedback loop");
+
+    for (name, cached) in variants().iter() {
+        let base = vm(&program, *cached);
+
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                (5..=9)
+                    .permutations(5)
+                    .map(|phases| {
+                        AmplifierChain::from_vm(&base, &phases, Topology::Looped)
+                            .run(0)
+                            .unwrap()
+                            .signal()
+                            .cloned()
+                    })
+                    .max()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, day2, day5, day7);
+criterion_main!(benches);
@@ -114,7 +114,7 @@ mod tests {
     )]
     fn part2_aoc(mass: &[Mass], expected: Fuel) {
         assert_eq!(part2(mass), expected);
-        assert_eq!(part2_iterator(&mass), expected);
+        assert_eq!(part2_iterator(mass), expected);
         assert_eq!(part2_recursive(mass), expected);
     }
 }
@@ -1,6 +1,10 @@
-use crate::vm::{ExecutionOption, Word, VM};
+use crate::vm::{Address, ExecutionOption, Outcome, Polynomial, Search, Symbolic, Value, VM};
 use anyhow::Context;
 use aoc_runner_derive::{aoc, aoc_generator};
+use std::ops::RangeInclusive;
+
+// Nouns and verbs are multiplied with the constants of the program, which may be large.
+type Word = i64;
 
 #[aoc_generator(day2)]
 fn parse_input(input: &str) -> anyhow::Result<Vec<Word>> {
@@ -13,44 +17,123 @@ fn parse_input(input: &str) -> anyhow::Result<Vec<Word>> {
 
 #[aoc(day2, part1)]
 pub fn part1(program: &[Word]) -> Word {
-    let mut program = program.to_vec(); // because we need mutability for the current solution; and the aoc generator doesn't support it
-
-    let mut vm = VM::new(&mut program);
+    let mut vm = VM::new(program);
 
-    vm.execute(ExecutionOption::default()).unwrap()
+    vm.execute(ExecutionOption::default())
+        .unwrap()
+        .word()
+        .unwrap()
 }
 
-// brute force
+// Where the program keeps its noun and verb.
+const NOUN: Address = 1;
+const VERB: Address = 2;
+
+// a bad noun or verb may send the program into a loop; give up on those
+const STEP_LIMIT: u64 = 10_000;
+
 #[aoc(day2, part2)]
-pub fn part2(program: &[Word]) -> Word {
+pub fn part2(program: &[Word]) -> anyhow::Result<Word> {
     let expected: Word = 19_690_720;
 
-    const MAX: Word = 64;
+    let (noun, verb) = find_noun_and_verb(program, expected, 0..=99)?;
+    Ok(100 * noun + verb)
+}
 
-    for noun in 0..=MAX {
-        for verb in 0..=MAX {
-            let mem = &mut program.to_vec();
-            mem[1] = noun;
-            mem[2] = verb;
+// Find a noun and verb in the range, for which the program leaves the target at address 0.
+//
+// The program is executed with the noun and verb as symbols, which gives address 0 as a
+// polynomial in the noun and verb, which is solved without running the program again. When the
+// program branches on the noun or verb, each pair is tried instead.
+//
+// A solution is still run once: the program may use the noun or verb as an address, which only
+// fails for some pairs. Each pair is tried as well when it does fail.
+pub fn find_noun_and_verb(
+    program: &[Word],
+    target: Word,
+    range: RangeInclusive<Word>,
+) -> anyhow::Result<(Word, Word)> {
+    let mut symbolic = Symbolic::new(program, &[NOUN, VERB]).with_step_limit(STEP_LIMIT);
+
+    let found = match symbolic.run().map(|()| symbolic.value(0)) {
+        Ok(Ok(Value::Known(output))) => match solve(&output, target, &range) {
+            Some(pair) if !produces(VM::new(program), pair, target) => {
+                brute_force(program, target, &range)
+            }
+            found => found,
+        },
+        _ => brute_force(program, target, &range),
+    };
 
-            let mut vm = VM::new(mem);
+    found.with_context(|| format!("No noun and verb in {:?} produce {}.", range, target))
+}
 
-            if let Ok(v) = vm.execute(ExecutionOption::default()) {
-                if v == expected {
-                    dbg!(noun, verb);
-                    return 100 * noun + verb;
-                }
-            }
-        }
+// Solve `output(noun, verb) == target`, where x0 is the noun and x1 the verb.
+fn solve(
+    output: &Polynomial<Word>,
+    target: Word,
+    range: &RangeInclusive<Word>,
+) -> Option<(Word, Word)> {
+    if output.degree(1) > 1 {
+        // evaluating the polynomial is still a lot cheaper than running the program
+        return range
+            .clone()
+            .flat_map(|noun| range.clone().map(move |verb| (noun, verb)))
+            .find(|&(noun, verb)| output.eval(&[noun, verb]) == Some(target));
     }
 
-    panic!("Unable to find compute")
+    // output = a(noun) + b(noun) * verb, so each noun has at most one verb, unless b is 0
+    let coefficients = output.coefficients(1);
+    let zero = Polynomial::constant(0);
+    let (a, b) = (&coefficients[0], coefficients.get(1).unwrap_or(&zero));
+
+    range.clone().find_map(|noun| {
+        let (a, b) = (a.eval(&[noun])?, b.eval(&[noun])?);
+        let rest = target.checked_sub(a)?;
+
+        let verb = match b {
+            0 if rest == 0 => *range.start(),
+            // no verb when b is 0, or when the verb doesn't fit, e.g. i64::MIN / -1
+            b if rest.checked_rem(b) == Some(0) => rest.checked_div(b)?,
+            _ => return None,
+        };
+
+        Some((noun, verb)).filter(|_| range.contains(&verb))
+    })
+}
+
+// Try each noun and verb, in order, and stop at the first pair found.
+fn brute_force(
+    program: &[Word],
+    target: Word,
+    range: &RangeInclusive<Word>,
+) -> Option<(Word, Word)> {
+    let pairs = range
+        .clone()
+        .flat_map(|noun| range.clone().map(move |verb| (noun, verb)));
+
+    Search::new(program)
+        .find(pairs, |vm, &pair| {
+            Some(()).filter(|_| produces(vm, pair, target))
+        })
+        .map(|(pair, ())| pair)
+}
+
+// Whether the program of the VM leaves the target at address 0, for the noun and verb.
+fn produces(vm: VM<Word>, (noun, verb): (Word, Word), target: Word) -> bool {
+    let mut vm = vm.with_step_limit(STEP_LIMIT);
+    vm.tape.write(NOUN, noun).unwrap();
+    vm.tape.write(VERB, verb).unwrap();
+
+    vm.execute(ExecutionOption::default()) == Ok(Outcome::Word(target))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::setup;
+    use crate::vm::assemble;
+    use parameterized::parameterized as pm;
 
     ide!();
 
@@ -60,11 +143,11 @@ mod tests {
 
     #[parameterized(
         input = {
-            &mut [1,9,10,3,2,3,11,0,99,30,40,50],
-            &mut [1,0,0,0,99],
-            &mut [2,3,0,3,99],
-            &mut [2,4,4,5,99,0],
-            &mut [1,1,1,4,99,5,6,0,99]
+            &[1,9,10,3,2,3,11,0,99,30,40,50],
+            &[1,0,0,0,99],
+            &[2,3,0,3,99],
+            &[2,4,4,5,99,0],
+            &[1,1,1,4,99,5,6,0,99]
         },
         expected = {
             3500,
@@ -74,24 +157,96 @@ mod tests {
             30,
         },
     )]
-    fn part1_aoc_from_start(input: &mut [Word], expected: Word) {
+    fn part1_aoc_from_start(input: &[Word], expected: Word) {
         let mut vm = VM::new(input);
-        assert_eq!(vm.execute(ExecutionOption::default()).unwrap(), expected);
+        assert_eq!(
+            vm.execute(ExecutionOption::default()),
+            Ok(Outcome::Word(expected))
+        );
     }
 
     #[test]
     fn part1_aoc_with_error_state() {
-        let mem = &mut inputs().unwrap();
+        let mut mem = inputs().unwrap();
         mem[1] = 12;
         mem[2] = 2;
 
         let mut vm = VM::new(mem);
 
-        assert_eq!(vm.execute(ExecutionOption::default()).unwrap(), 3_895_705);
+        assert_eq!(
+            vm.execute(ExecutionOption::default()),
+            Ok(Outcome::Word(3_895_705))
+        );
+    }
+
+    #[test]
+    fn part2_aoc() {
+        assert_eq!(part2(&inputs().unwrap()).unwrap(), 6417);
     }
 
     #[test]
     fn part2_aoc_brute_force() {
-        assert_eq!(part2(&mut inputs().unwrap()), 6417);
+        assert_eq!(
+            brute_force(&inputs().unwrap(), 19_690_720, &(0..=99)),
+            Some((64, 17))
+        );
+    }
+
+    #[test]
+    fn symbolic_output() {
+        let program = inputs().unwrap();
+        let mut symbolic = Symbolic::new(&program, &[NOUN, VERB]);
+        symbolic.run().unwrap();
+
+        match symbolic.value(0) {
+            Ok(Value::Known(output)) => assert_eq!(output.to_string(), "303750*x0 + x1 + 250703"),
+            value => panic!("Unexpected output {:?}", value),
+        }
+    }
+
+    // doubles the sum of the noun and verb, unless it's less than 10
+    fn branching() -> Vec<Word> {
+        assemble(
+            "
+                    add #0, #0, [sum]
+                    lt [sum], #10, [small]
+                    jt [small], #done
+                    mul [sum], #2, [0]
+            done:   hlt
+            sum:    data 0
+            small:  data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    #[pm(target = { 30, 20, 1101, 2 }, expected = { Some((6, 9)), Some((1, 9)), Some((0, 0)), None })]
+    fn falls_back_to_brute_force(target: Word, expected: Option<(Word, Word)>) {
+        let found = find_noun_and_verb(&branching(), target, 0..=9);
+
+        assert_eq!(found.ok(), expected);
+    }
+
+    #[test]
+    fn solutions_are_run() {
+        // adds the words at the noun and verb, so a negative noun fails, and outputs the verb
+        let program = [1, 0, 0, 9, 1001, 2, 0, 0, 99, 0];
+
+        assert_eq!(find_noun_and_verb(&program, 3, -5..=5).ok(), Some((0, 3)));
+    }
+
+    #[test]
+    fn verb_overflows() {
+        // outputs -noun * verb, so noun 1 would need verb -i64::MIN
+        let program = [1102, 0, 0, 0, 1002, 0, -1, 0, 99];
+
+        assert!(find_noun_and_verb(&program, Word::MIN, 0..=99).is_err());
+    }
+
+    #[test]
+    fn nothing_found() {
+        let error = find_noun_and_verb(&inputs().unwrap(), 1, 0..=99).unwrap_err();
+
+        assert_eq!(error.to_string(), "No noun and verb in 0..=99 produce 1.");
     }
 }
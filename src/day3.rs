@@ -105,7 +105,7 @@ impl GridMatches {
     }
 
     fn contains(&self, line: usize) -> bool {
-        self.indexed_map.get(&line).is_some()
+        self.indexed_map.contains_key(&line)
     }
 }
 
@@ -132,7 +132,7 @@ impl Eq for CableMatch {}
 
 impl PartialOrd for CableMatch {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.length.0.partial_cmp(&other.length.0)
+        Some(self.cmp(other))
     }
 }
 
@@ -170,7 +170,7 @@ fn draw_wires(circuit: &mut HashMap<(i32, i32), Vec<CableMatch>>, wires: &[Vec<T
                 // push the line number, + the current line length.
                 circuit
                     .entry((coord.0, coord.1))
-                    .or_insert_with(|| vec![])
+                    .or_default()
                     .push(CableMatch::new(n, incr));
 
                 (coord.0, coord.1, incr)
@@ -1,6 +1,10 @@
-use crate::vm::{ExecutionOption, Word, VM};
+use crate::vm::{Address, State, VmError, VM};
 use anyhow::{Context, Result};
 use aoc_runner_derive::{aoc, aoc_generator};
+use std::fmt::{Display, Formatter};
+
+// The diagnostic program only works with small numbers.
+type Word = i32;
 
 #[aoc_generator(day5)]
 fn parse_input(input: &str) -> Result<Vec<Word>> {
@@ -11,25 +15,80 @@ fn parse_input(input: &str) -> Result<Vec<Word>> {
         .context("Unable to parse input.")
 }
 
+// Why the diagnostic program didn't produce a diagnostic code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticError {
+    // the check of the output instruction at pc didn't output 0
+    CheckFailed { pc: Address, value: Word },
+    // the program halted without any output
+    NoCode { pc: Address },
+    Vm(VmError<Word>),
+}
+
+impl Display for DiagnosticError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiagnosticError::CheckFailed { pc, value } => {
+                write!(f, "Diagnostic check failed with {} (pc: {}).", value, pc)
+            }
+            DiagnosticError::NoCode { pc } => {
+                write!(f, "Program halted without diagnostic code (pc: {}).", pc)
+            }
+            DiagnosticError::Vm(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for DiagnosticError {}
+
+impl From<VmError<Word>> for DiagnosticError {
+    fn from(error: VmError<Word>) -> Self {
+        DiagnosticError::Vm(error)
+    }
+}
+
+// Run the TEST program for the system, and return its diagnostic code: the last output. Every
+// output before it is the result of a check, which must be 0.
+pub fn diagnostics(program: &[Word], system: Word) -> Result<Word, DiagnosticError> {
+    let mut vm = VM::with_inputs(program, vec![system]);
+    // the pc of the latest output instruction, and its value
+    let mut latest: Option<(Address, Word)> = None;
+
+    loop {
+        let pc = vm.pc;
+
+        match vm.step()? {
+            State::Running => continue,
+            State::Output(value) => match latest.replace((pc, value)) {
+                Some((pc, value)) if value != 0 => {
+                    return Err(DiagnosticError::CheckFailed { pc, value })
+                }
+                _ => continue,
+            },
+            State::NeedsInput => return Err(VmError::InputExhausted { pc }.into()),
+            State::Halted => {
+                return latest
+                    .map(|(_, code)| code)
+                    .ok_or(DiagnosticError::NoCode { pc })
+            }
+        }
+    }
+}
+
 #[aoc(day5, part1)]
 fn part1(input: &[Word]) -> Result<Word> {
-    let mut program = input.to_vec(); // because we need mutability for the current solution; and the aoc generator doesn't support it
-
-    let mut vm = VM::with_inputs(&mut program, vec![1]);
-    vm.execute(ExecutionOption::OutputByTapeOutput)
+    Ok(diagnostics(input, 1)?)
 }
 
 #[aoc(day5, part2)]
 fn part2(input: &[Word]) -> Result<Word> {
-    let mut program = input.to_vec(); // because we need mutability for the current solution; and the aoc generator doesn't support it
-
-    let mut vm = VM::with_inputs(&mut program, vec![5]);
-    vm.execute(ExecutionOption::OutputByTapeOutput)
+    Ok(diagnostics(input, 5)?)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::vm::{assemble, ExecutionOption, Outcome};
     use parameterized::parameterized as pm;
 
     mod my_result {
@@ -44,37 +103,54 @@ mod tests {
 
         #[test]
         fn part1() {
-            let mut memory = problem_input().unwrap();
-            let mut vm = VM::with_inputs(&mut memory, vec![1]);
+            let memory = problem_input().unwrap();
+            let mut vm = VM::with_inputs(memory, vec![1]);
             assert_eq!(
-                vm.execute(ExecutionOption::OutputByTapeOutput).unwrap(),
-                5182797
+                vm.execute(ExecutionOption::OutputByTapeOutput),
+                Ok(Outcome::Word(5182797))
             );
         }
 
+        #[test]
+        fn part1_all_outputs() {
+            let memory = problem_input().unwrap();
+            let mut vm = VM::with_inputs(memory, vec![1]);
+            let outputs = vm.execute(ExecutionOption::AllOutputs).unwrap().outputs();
+
+            // the test results, which should all be 0, then the diagnostic code
+            let (code, tests) = outputs.as_deref().and_then(<[_]>::split_last).unwrap();
+            assert!(tests.iter().all(|&test| test == 0));
+            assert_eq!(*code, 5182797);
+        }
+
         #[test]
         fn part2() {
-            let mut memory = problem_input().unwrap();
-            let mut vm = VM::with_inputs(&mut memory, vec![5]);
+            let memory = problem_input().unwrap();
+            let mut vm = VM::with_inputs(memory, vec![5]);
             assert_eq!(
-                vm.execute(ExecutionOption::OutputByTapeOutput).unwrap(),
-                12077198
+                vm.execute(ExecutionOption::OutputByTapeOutput),
+                Ok(Outcome::Word(12077198))
             );
         }
+
+        #[pm(system = { 1, 5 }, expected = { 5182797, 12077198 })]
+        fn diagnostic_code(system: Word, expected: Word) {
+            let memory = problem_input().unwrap();
+            assert_eq!(diagnostics(&memory, system), Ok(expected));
+        }
     }
 
-    #[cfg(test)]
-    mod tests {
+    mod examples {
         use super::*;
 
         ide!();
 
         #[pm(
         program = {
-            &mut [3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, - 1, 0, 1, 9],
-            &mut [3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, - 1, 0, 1, 9],
-            &mut [3, 3, 1105, - 1, 9, 1101, 0, 0, 12, 4, 12, 99, 1],
-            &mut [3, 3, 1105, - 1, 9, 1101, 0, 0, 12, 4, 12, 99, 1],
+            &[3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, - 1, 0, 1, 9],
+            &[3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, - 1, 0, 1, 9],
+            &[3, 3, 1105, - 1, 9, 1101, 0, 0, 12, 4, 12, 99, 1],
+            &[3, 3, 1105, - 1, 9, 1101, 0, 0, 12, 4, 12, 99, 1],
         },
         input = {
             1,
@@ -88,11 +164,69 @@ mod tests {
             1,
             0,
         })]
-        fn jump_if_true_mirror(program: &mut [Word], input: Word, expected: Word) {
+        fn jump_if_true_mirror(program: &[Word], input: Word, expected: Word) {
             let mut vm = VM::with_inputs(program, vec![input]);
             assert_eq!(
-                vm.execute(ExecutionOption::OutputByTapeOutput).unwrap(),
-                expected
+                vm.execute(ExecutionOption::OutputByTapeOutput),
+                Ok(Outcome::Word(expected))
+            );
+        }
+    }
+
+    mod diagnostics {
+        use super::*;
+
+        ide!();
+
+        // outputs its checks, then the input as code
+        fn program(checks: &[Word]) -> Vec<Word> {
+            let checks = checks
+                .iter()
+                .map(|check| format!("out #{}\n", check))
+                .collect::<String>();
+
+            assemble(&format!(
+                "in [code]\n{}out [code]\nhlt\ncode: data 0",
+                checks
+            ))
+            .unwrap()
+        }
+
+        #[pm(checks = {
+            &[],
+            &[0],
+            &[0, 0, 0],
+        })]
+        fn passes(checks: &[Word]) {
+            assert_eq!(diagnostics(&program(checks), 42), Ok(42));
+        }
+
+        #[pm(checks = {
+            &[3],
+            &[0, 0, -1],
+            &[0, 7, 0],
+        }, expected = {
+            DiagnosticError::CheckFailed { pc: 2, value: 3 },
+            DiagnosticError::CheckFailed { pc: 6, value: -1 },
+            DiagnosticError::CheckFailed { pc: 4, value: 7 },
+        })]
+        fn fails(checks: &[Word], expected: DiagnosticError) {
+            assert_eq!(diagnostics(&program(checks), 42), Err(expected));
+        }
+
+        #[test]
+        fn no_code() {
+            assert_eq!(
+                diagnostics(&[3, 3, 99, 0], 1),
+                Err(DiagnosticError::NoCode { pc: 2 })
+            );
+        }
+
+        #[test]
+        fn vm_error() {
+            assert_eq!(
+                diagnostics(&[3, 5, 3, 5, 99, 0], 1),
+                Err(DiagnosticError::Vm(VmError::InputExhausted { pc: 2 }))
             );
         }
     }
@@ -47,12 +47,13 @@ fn add_node(
     map: &mut HashMap<String, NodeIndex>,
     parent: String,
 ) -> NodeIndex {
-    if map.contains_key(&parent) {
-        *map.get(parent.as_str()).unwrap()
-    } else {
-        let id = graph.add_node(parent.clone());
-        map.insert(parent, id);
-        id
+    match map.get(&parent) {
+        Some(&id) => id,
+        None => {
+            let id = graph.add_node(parent.clone());
+            map.insert(parent, id);
+            id
+        }
     }
 }
 
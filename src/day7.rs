@@ -1,7 +1,11 @@
-use crate::vm::{ExecutionOption, Word, VM};
+use crate::vm::{AmplifierChain, Search, Topology};
 use anyhow::{Context, Result};
 use aoc_runner_derive::{aoc, aoc_generator};
 use itertools::Itertools;
+use std::ops::RangeInclusive;
+
+// Amplifier signals grow with each amplifier in the chain.
+type Word = i64;
 
 #[aoc_generator(day7)]
 fn parse_input(input: &str) -> Result<Vec<Word>> {
@@ -12,28 +16,94 @@ fn parse_input(input: &str) -> Result<Vec<Word>> {
         .context("Unable to parse input.")
 }
 
-fn run_vm_with(program: &[Word], phase_settings: i32, input_signal: i32) -> Result<Word> {
-    let mut memory = program.to_vec();
-    let mut vm = VM::with_inputs(&mut memory, vec![phase_settings, input_signal]);
-    vm.execute(ExecutionOption::OutputByTapeOutput)
-        .context("Invalid calculation")
+// Try each permutation of the phase settings, and find the strongest signal sent to the thrusters.
+fn max_thruster_signal(
+    program: &[Word],
+    phase_settings: RangeInclusive<Word>,
+    topology: Topology,
+) -> Result<Word> {
+    let permutations = phase_settings.permutations(5);
+
+    Search::new(program)
+        .max(permutations, |vm, phase_settings| {
+            AmplifierChain::from_vm(&vm, phase_settings, topology)
+                .run(0)?
+                .signal()
+                .cloned()
+                .context("No signal was sent to the thrusters.")
+        })?
+        .map(|(_, signal)| signal)
+        .context("Unable to compute the maximum thruster signal.")
 }
 
 #[aoc(day7, part1)]
 fn part1(program: &[Word]) -> Result<Word> {
-    // hihihi :P
-    (0..=4)
-        .permutations(5)
-        .flat_map(|amplifier_controls| {
-            run_vm_with(program, amplifier_controls[0], 0).and_then(|out| {
-                run_vm_with(program, amplifier_controls[1], out).and_then(|out| {
-                    run_vm_with(program, amplifier_controls[2], out).and_then(|out| {
-                        run_vm_with(program, amplifier_controls[3], out)
-                            .and_then(|out| run_vm_with(program, amplifier_controls[4], out))
-                    })
-                })
-            })
-        })
-        .max()
-        .context("Unable to compute the maximum amplifier output.")
+    max_thruster_signal(program, 0..=4, Topology::Linear)
+}
+
+#[aoc(day7, part2)]
+fn part2(program: &[Word]) -> Result<Word> {
+    max_thruster_signal(program, 5..=9, Topology::Looped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parameterized::parameterized as pm;
+
+    ide!();
+
+    mod my_result {
+        use super::*;
+        use crate::setup;
+
+        ide!();
+
+        fn problem_input() -> anyhow::Result<Vec<Word>> {
+            setup(7, parse_input)
+        }
+
+        #[test]
+        fn part1() {
+            assert_eq!(super::part1(&problem_input().unwrap()).unwrap(), 366_376);
+        }
+
+        #[test]
+        fn part2() {
+            assert_eq!(super::part2(&problem_input().unwrap()).unwrap(), 21_596_786);
+        }
+    }
+
+    #[pm(program = {
+        &[3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0],
+        &[3,23,3,24,1002,24,10,24,1002,23,-1,23,101,5,23,23,1,24,23,23,4,23,99,0,0],
+    }, phase_settings = {
+        &[4,3,2,1,0],
+        &[0,1,2,3,4],
+    }, expected = {
+        43210,
+        54321,
+    })]
+    fn linear(program: &[Word], phase_settings: &[Word], expected: Word) {
+        let mut chain = AmplifierChain::new(program, phase_settings, Topology::Linear);
+        assert_eq!(chain.run(0).unwrap().signal(), Some(&expected));
+        assert_eq!(part1(program).unwrap(), expected);
+    }
+
+    #[pm(program = {
+        &[3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5],
+        &[3,52,1001,52,-5,52,3,53,1,52,56,54,1007,54,5,55,1005,55,26,1001,54,-5,54,1105,1,12,1,53,
+          54,53,1008,54,0,55,1001,55,1,55,2,53,55,53,4,53,1001,56,-1,56,1005,56,6,99,0,0,0,0,10],
+    }, phase_settings = {
+        &[9,8,7,6,5],
+        &[9,7,8,5,6],
+    }, expected = {
+        139_629_729,
+        18216,
+    })]
+    fn feedback_loop(program: &[Word], phase_settings: &[Word], expected: Word) {
+        let mut chain = AmplifierChain::new(program, phase_settings, Topology::Looped);
+        assert_eq!(chain.run(0).unwrap().signal(), Some(&expected));
+        assert_eq!(part2(program).unwrap(), expected);
+    }
 }
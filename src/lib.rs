@@ -14,7 +14,7 @@ mod day7;
 mod day8;
 
 // the intcode vm
-mod vm;
+pub mod vm;
 
 aoc_lib! { year = 2019 }
 
@@ -1,3 +1,76 @@
-use aoc_runner_derive::aoc_main;
+use anyhow::{Context, Result};
+use aoc19::vm;
+use std::path::Path;
 
-aoc_main! { lib = aoc19 }
+// The runner generated by aoc-runner, which solves every day.
+mod runner {
+    use aoc_runner_derive::aoc_main;
+
+    aoc_main! { lib = aoc19 }
+
+    pub fn run() {
+        main()
+    }
+}
+
+const USAGE: &str = "Usage:
+    aoc19                        solve every day
+    aoc19 disasm <day|path>      disassemble an intcode program
+    aoc19 cfg <day|path>         print the control flow graph of an intcode program, as DOT
+    aoc19 debug <day|path> [inputs..]
+                                 debug an intcode program, see `help` in the debugger
+    aoc19 ascii <day|path>       play an intcode program which communicates in ASCII";
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [] => runner::run(),
+        ["disasm", program] => print!("{}", vm::disassemble(&load_program(program)?)),
+        ["cfg", program] => print!("{}", vm::analyze(&load_program(program)?).to_dot()),
+        ["debug", program, inputs @ ..] => {
+            let inputs = inputs
+                .iter()
+                .map(|v| v.parse())
+                .collect::<Result<Vec<i64>, _>>()
+                .context("Unable to parse inputs.")?;
+
+            let vm = vm::VM::with_inputs(load_program(program)?, inputs);
+            let stdin = std::io::stdin();
+
+            vm::Debugger::new(vm).repl(stdin.lock(), std::io::stdout())?;
+        }
+        ["ascii", program] => {
+            let vm = vm::VM::new(load_program(program)?);
+            let stdin = std::io::stdin();
+
+            vm::Console::new(vm).play(stdin.lock(), std::io::stdout())?;
+        }
+        _ => anyhow::bail!("{}", USAGE),
+    }
+
+    Ok(())
+}
+
+// Load an intcode program, either by day number from `input/2019`, or from a path.
+fn load_program(program: &str) -> Result<Vec<i64>> {
+    let path = match program.parse::<u8>() {
+        Ok(day) => Path::new("input/2019").join(format!("day{}.txt", day)),
+        Err(_) => Path::new(program).to_path_buf(),
+    };
+
+    let input = std::fs::read_to_string(&path)
+        .with_context(|| format!("Unable to read {}.", path.display()))?;
+
+    input
+        .trim()
+        .split(',')
+        .map(|v| v.trim().parse())
+        .collect::<Result<Vec<_>, std::num::ParseIntError>>()
+        .context("Unable to parse program.")
+}
@@ -0,0 +1,165 @@
+use crate::vm::{State, VmError, Word, VM};
+
+// How the stages of an amplifier chain are connected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Topology {
+    // the outputs of each stage are the inputs of the next stage
+    Linear,
+    // like linear, but the outputs of the last stage are also fed back to the first stage
+    Looped,
+}
+
+// A chain of VMs, each running the same program. Each stage receives its phase setting
+// as first input, and the outputs of the previous stage as its next inputs.
+pub struct AmplifierChain<W: Word> {
+    stages: Vec<VM<W>>,
+    topology: Topology,
+}
+
+// The outputs of each stage of the chain, in emission order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainOutput<W: Word> {
+    pub stages: Vec<Vec<W>>,
+}
+
+impl<W: Word> ChainOutput<W> {
+    // The last output of the last stage.
+    pub fn signal(&self) -> Option<&W> {
+        self.stages.last().and_then(|outputs| outputs.last())
+    }
+}
+
+impl<W: Word> AmplifierChain<W> {
+    // A chain with a stage for each phase setting.
+    pub fn new(program: &[W], phase_settings: &[W], topology: Topology) -> Self {
+        Self::from_vm(&VM::new(program), phase_settings, topology)
+    }
+
+    // A chain with a stage for each phase setting, each forked from the given VM.
+    pub fn from_vm(vm: &VM<W>, phase_settings: &[W], topology: Topology) -> Self {
+        let stages = phase_settings
+            .iter()
+            .map(|phase| {
+                let mut stage = vm.fork();
+                stage.push_input(phase.clone());
+                stage
+            })
+            .collect();
+
+        Self { stages, topology }
+    }
+
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    // Send the input signal to the first stage, and run the chain until the last stage halts.
+    // If no stage can make progress before that, the chain is stuck waiting for input.
+    pub fn run(&mut self, input: W) -> Result<ChainOutput<W>, VmError<W>> {
+        let len = self.stages.len();
+        let mut outputs = vec![Vec::new(); len];
+        let mut halted = vec![false; len];
+
+        if let Some(first) = self.stages.first_mut() {
+            first.push_input(input);
+        }
+
+        while halted.last() == Some(&false) {
+            let mut progress = false;
+
+            for n in 0..len {
+                loop {
+                    match self.stages[n].run()? {
+                        State::Output(value) => {
+                            if let Some(next) = self.next(n) {
+                                self.stages[next].push_input(value.clone());
+                            }
+
+                            outputs[n].push(value);
+                            progress = true;
+                        }
+                        // either halted, or waiting for input
+                        state => {
+                            halted[n] = state == State::Halted;
+                            break;
+                        }
+                    }
+                }
+            }
+
+            if !progress && halted.last() == Some(&false) {
+                let waiting = halted.iter().position(|h| !h).unwrap_or_default();
+
+                return Err(VmError::InputExhausted {
+                    pc: self.stages[waiting].pc,
+                });
+            }
+        }
+
+        Ok(ChainOutput { stages: outputs })
+    }
+
+    // The stage which receives the outputs of stage `n`, if any.
+    fn next(&self, n: usize) -> Option<usize> {
+        match self.topology {
+            Topology::Linear if n + 1 == self.stages.len() => None,
+            Topology::Linear => Some(n + 1),
+            Topology::Looped => Some((n + 1) % self.stages.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parameterized::parameterized as pm;
+
+    ide!();
+
+    // outputs the sum of its two inputs
+    const ADDER: [i64; 14] = [3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0];
+
+    #[pm(phase_settings = {
+        &[5],
+        &[1, 2, 3],
+        &[1, 1, 1, 1, 1, 1, 1, 1],
+    }, expected = {
+        vec![vec![15]],
+        vec![vec![11], vec![13], vec![16]],
+        (11..=18).map(|n| vec![n]).collect(),
+    })]
+    fn linear(phase_settings: &[i64], expected: Vec<Vec<i64>>) {
+        let mut chain = AmplifierChain::new(&ADDER, phase_settings, Topology::Linear);
+        let output = chain.run(10).unwrap();
+
+        assert_eq!(output.signal(), expected.last().and_then(|v| v.last()));
+        assert_eq!(output.stages, expected);
+    }
+
+    #[test]
+    fn looped() {
+        // counts down its phase setting, passing on the (incremented) signal each iteration
+        let program = [
+            3, 20, 3, 21, 101, 1, 21, 21, 4, 21, 101, -1, 20, 20, 1005, 20, 2, 99, 0, 0, 0, 0,
+        ];
+
+        let mut chain = AmplifierChain::new(&program, &[3, 3], Topology::Looped);
+        let output = chain.run(0).unwrap();
+
+        assert_eq!(output.stages, vec![vec![1, 3, 5], vec![2, 4, 6]]);
+        assert_eq!(output.signal(), Some(&6));
+    }
+
+    #[test]
+    fn stuck() {
+        // wants a third input, which never arrives
+        let program = [3, 0, 3, 0, 3, 0, 99];
+
+        let mut chain = AmplifierChain::new(&program, &[1, 2], Topology::Linear);
+        assert_eq!(chain.run(0), Err(VmError::InputExhausted { pc: 4 }));
+    }
+}
@@ -0,0 +1,357 @@
+use crate::vm::opcode::*;
+use crate::vm::{Address, Opcode, Word, PARAM1, PARAM2, PARAM3};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+// An error in the assembly source, at the given line (counting from 1).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (line: {}).", self.message, self.line)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// (mnemonic, opcode, number of parameters which are read, whether the last parameter is written)
+const MNEMONICS: [(&str, Opcode, usize, bool); 10] = [
+    ("add", OPCODE_ADD, 2, true),
+    ("mul", OPCODE_MUL, 2, true),
+    ("in", OPCODE_INPUT, 0, true),
+    ("out", OPCODE_OUTPUT, 1, false),
+    ("jt", OPCODE_JUMP_IF_TRUE, 2, false),
+    ("jf", OPCODE_JUMP_IF_FALSE, 2, false),
+    ("lt", OPCODE_LT, 2, true),
+    ("eq", OPCODE_EQ, 2, true),
+    ("arb", OPCODE_ADJUST_RELATIVE_BASE, 1, false),
+    ("hlt", OPCODE_RET, 0, false),
+];
+
+// The opcode of an instruction, by its mnemonic.
+pub(crate) fn opcode_of(mnemonic: &str) -> Option<Opcode> {
+    MNEMONICS
+        .iter()
+        .find(|(name, ..)| *name == mnemonic)
+        .map(|&(_, opcode, ..)| opcode)
+}
+
+// A word of the program, which may refer to a label which isn't defined yet.
+enum Value<'a, W> {
+    Number(W),
+    Label(&'a str),
+}
+
+// Translate assembly text into a program. Mnemonics and parameters are written like the
+// output of `disassemble`:
+//
+//             add #3, #0, [counter]
+//     loop:   out [counter]                 ; count down from 3
+//             add [counter], #-1, [counter]
+//             jt [counter], #loop
+//             hlt
+//     counter:
+//             data 0
+//
+// Parameters are `[addr]` (position), `#value` (immediate) or `rb+offset` (relative).
+// A label is defined by `name:`, and stands for the address of whatever follows it. It can
+// be used in place of any number, except for relative offsets. The `data` directive places
+// its words in the program as they are, and `;` starts a comment.
+pub fn assemble<W: Word>(source: &str) -> Result<Vec<W>, AsmError> {
+    let mut words: Vec<(usize, Value<W>)> = Vec::new();
+    let mut labels: HashMap<&str, Address> = HashMap::new();
+
+    // first pass: lay out the words, and find the address of every label
+    for (n, line) in source.lines().enumerate() {
+        let line_no = n + 1;
+        let error = |message: String| AsmError {
+            line: line_no,
+            message,
+        };
+
+        let mut rest = line.split(';').next().unwrap_or_default().trim();
+
+        while let Some((label, tail)) = split_label(rest) {
+            if labels.insert(label, words.len()).is_some() {
+                return Err(error(format!("Label '{}' is defined twice", label)));
+            }
+
+            rest = tail.trim_start();
+        }
+
+        if rest.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = match rest.find(char::is_whitespace) {
+            Some(i) => (&rest[..i], rest[i..].trim()),
+            None => (rest, ""),
+        };
+        let operands: Vec<&str> = match operands {
+            "" => Vec::new(),
+            operands => operands.split(',').map(str::trim).collect(),
+        };
+
+        if mnemonic == "data" {
+            for operand in operands {
+                words.push((line_no, value(operand).map_err(error)?));
+            }
+
+            continue;
+        }
+
+        let &(_, opcode, reads, writes) = MNEMONICS
+            .iter()
+            .find(|(name, ..)| *name == mnemonic)
+            .ok_or_else(|| error(format!("Unknown mnemonic '{}'", mnemonic)))?;
+
+        let arity = reads + writes as usize;
+        if operands.len() != arity {
+            return Err(error(format!(
+                "'{}' takes {} parameters, but {} were given",
+                mnemonic,
+                arity,
+                operands.len()
+            )));
+        }
+
+        let mut opcode = opcode;
+        let mut params = Vec::with_capacity(arity);
+
+        for (i, (operand, scale)) in operands.iter().zip(&[PARAM1, PARAM2, PARAM3]).enumerate() {
+            let (mode, value) = param(operand).map_err(error)?;
+
+            if mode == 1 && i >= reads {
+                return Err(error(format!(
+                    "Parameter '{}' is written to, and can't be immediate",
+                    operand
+                )));
+            }
+
+            opcode += mode * 100 * scale;
+            params.push((line_no, value));
+        }
+
+        words.push((line_no, Value::Number(W::from_i32(opcode).unwrap())));
+        words.extend(params);
+    }
+
+    // second pass: replace labels by their address
+    words
+        .into_iter()
+        .map(|(line, value)| match value {
+            Value::Number(w) => Ok(w),
+            Value::Label(label) => labels
+                .get(label)
+                .and_then(|&addr| W::from_usize(addr))
+                .ok_or_else(|| AsmError {
+                    line,
+                    message: format!("Unknown label '{}'", label),
+                }),
+        })
+        .collect()
+}
+
+// Split `name: rest` into the label name and the rest of the line.
+fn split_label(line: &str) -> Option<(&str, &str)> {
+    let colon = line.find(':')?;
+    let label = line[..colon].trim();
+
+    if is_label(label) {
+        Some((label, &line[colon + 1..]))
+    } else {
+        None
+    }
+}
+
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// A parameter and its mode.
+fn param<W: Word>(operand: &str) -> Result<(Opcode, Value<'_, W>), String> {
+    if operand.starts_with('[') && operand.ends_with(']') {
+        Ok((0, value(&operand[1..operand.len() - 1])?))
+    } else if let Some(immediate) = operand.strip_prefix('#') {
+        Ok((1, value(immediate)?))
+    } else if let Some(offset) = operand.strip_prefix("rb") {
+        let offset = match offset.trim() {
+            "" => W::zero(),
+            offset => number(offset.strip_prefix('+').unwrap_or(offset))?,
+        };
+
+        Ok((2, Value::Number(offset)))
+    } else {
+        Err(format!(
+            "Invalid parameter '{}', expected [addr], #value or rb+offset",
+            operand
+        ))
+    }
+}
+
+fn value<W: Word>(token: &str) -> Result<Value<'_, W>, String> {
+    let token = token.trim();
+
+    if is_label(token) {
+        Ok(Value::Label(token))
+    } else {
+        number(token).map(Value::Number)
+    }
+}
+
+fn number<W: Word>(token: &str) -> Result<W, String> {
+    token
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid number '{}'", token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{
+        BinopInstr, ExecutionOption, InParam, Instruction, Memory, OutParam, Outcome, VM,
+    };
+    use parameterized::parameterized as pm;
+    use proptest::prelude::*;
+
+    ide!();
+
+    #[pm(source = {
+        "add [9], #3, [9]",
+        "mul #2, rb-1, rb+4",
+        "in rb",
+        "out #-7",
+        "jt #1, #12\njf [5], #0",
+        "lt [1], [2], [3]\neq rb+0, #3, [5]",
+        "arb #19\nhlt",
+    }, expected = {
+        vec![1001, 9, 3, 9],
+        vec![22102, 2, -1, 4],
+        vec![203, 0],
+        vec![104, -7],
+        vec![1105, 1, 12, 1006, 5, 0],
+        vec![7, 1, 2, 3, 1208, 0, 3, 5],
+        vec![109, 19, 99],
+    })]
+    fn instructions(source: &str, expected: Vec<i64>) {
+        assert_eq!(assemble::<i64>(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn labels_and_data() {
+        let source = "
+                    add #3, #0, [counter]
+            loop:   out [counter]                 ; count down from 3
+                    add [counter], #-1, [counter]
+                    jt [counter], #loop
+                    hlt
+            counter:
+                    data 0
+        ";
+
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            program,
+            [1101, 3, 0, 14, 4, 14, 1001, 14, -1, 14, 1005, 14, 4, 99, 0]
+        );
+
+        let mut vm = VM::<i64>::new(program);
+        assert_eq!(
+            vm.execute(ExecutionOption::OutputByTapeOutput),
+            Ok(Outcome::Word(1))
+        );
+    }
+
+    #[test]
+    fn forward_and_repeated_labels() {
+        let source = "start: a: b: jt #1, #end\ndata start, a, b\nend: hlt";
+
+        assert_eq!(
+            assemble::<i32>(source).unwrap(),
+            vec![1105, 1, 6, 0, 0, 0, 99]
+        );
+    }
+
+    #[pm(source = {
+        "nop",
+        "add [1], [2]",
+        "add [1], [2], #3",
+        "out 5",
+        "out [x",
+        "jt #1, #nowhere",
+        "out rb+x",
+        "data 1, two",
+        "a: hlt\na: hlt",
+        "hlt\n\nout #99999999999",
+    }, line = {
+        1, 1, 1, 1, 1, 1, 1, 1, 2, 3,
+    })]
+    fn errors(source: &str, line: usize) {
+        assert_eq!(assemble::<i32>(source).unwrap_err().line, line);
+    }
+
+    fn in_param() -> impl Strategy<Value = InParam<i64>> {
+        prop_oneof![
+            any::<i64>().prop_map(InParam::Position),
+            any::<i64>().prop_map(InParam::Immediate),
+            any::<i64>().prop_map(InParam::Relative),
+        ]
+    }
+
+    fn out_param() -> impl Strategy<Value = OutParam<i64>> {
+        prop_oneof![
+            any::<i64>().prop_map(OutParam::Position),
+            any::<i64>().prop_map(OutParam::Relative),
+        ]
+    }
+
+    fn instruction() -> impl Strategy<Value = Instruction<i64>> {
+        let in2 = || [in_param(), in_param()];
+
+        prop_oneof![
+            (in2(), out_param()).prop_map(|(p, o)| Instruction::Binop(BinopInstr::Add, p, o)),
+            (in2(), out_param()).prop_map(|(p, o)| Instruction::Binop(BinopInstr::Mul, p, o)),
+            out_param().prop_map(Instruction::Input),
+            in_param().prop_map(Instruction::Output),
+            in2().prop_map(Instruction::JumpIfTrue),
+            in2().prop_map(Instruction::JumpIfFalse),
+            (in2(), out_param()).prop_map(|(p, o)| Instruction::LessThan(p, o)),
+            (in2(), out_param()).prop_map(|(p, o)| Instruction::Equals(p, o)),
+            in_param().prop_map(Instruction::AdjustRelativeBase),
+            Just(Instruction::Ret),
+        ]
+    }
+
+    proptest! {
+        // assembling the text of decoded instructions gives back the same instructions
+        #[test]
+        fn round_trip(instructions in proptest::collection::vec(instruction(), 1..32)) {
+            let source = instructions
+                .iter()
+                .map(|instr| instr.to_string())
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            let memory = Memory::new(assemble::<i64>(&source).unwrap());
+            let mut pc = 0;
+
+            for expected in &instructions {
+                let instr = Instruction::fetch(&memory, pc).unwrap();
+                prop_assert_eq!(&instr, expected);
+                pc += instr.len();
+            }
+
+            prop_assert_eq!(pc, memory.len());
+        }
+    }
+}
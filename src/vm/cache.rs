@@ -0,0 +1,153 @@
+use crate::vm::{Address, Instruction, Word};
+use std::sync::Arc;
+
+// Instructions take at most this many words.
+const MAX_INSTRUCTION_LEN: usize = 4;
+
+// Instructions at or beyond this address aren't cached, so the cache stays a plain vector.
+pub(crate) const MAX_CACHED_ADDRESS: Address = 1 << 16;
+
+// Number of instructions in a chunk of the cache.
+const CHUNK_SIZE: usize = 64;
+
+type Chunk<W> = Arc<Vec<Option<Instruction<W>>>>;
+
+// Decoded instructions by address, so an instruction is decoded once, instead of each time
+// it's executed. It's kept by the memory, which forgets the instructions overlapping any
+// address which is written to (self-modifying code).
+//
+// Like memory pages, chunks of the cache are shared between clones, so a forked VM starts out
+// with the instructions decoded by its parent.
+#[derive(Debug, Clone)]
+pub(crate) struct InstructionCache<W: Word> {
+    chunks: Vec<Option<Chunk<W>>>,
+}
+
+impl<W: Word> InstructionCache<W> {
+    pub(crate) fn new() -> Self {
+        Self { chunks: Vec::new() }
+    }
+
+    pub(crate) fn get(&self, pc: Address) -> Option<&Instruction<W>> {
+        self.chunks.get(pc / CHUNK_SIZE)?.as_ref()?[pc % CHUNK_SIZE].as_ref()
+    }
+
+    pub(crate) fn insert(&mut self, pc: Address, instruction: Instruction<W>) {
+        if pc >= MAX_CACHED_ADDRESS {
+            return;
+        }
+
+        let n = pc / CHUNK_SIZE;
+        if n >= self.chunks.len() {
+            self.chunks.resize(n + 1, None);
+        }
+
+        let chunk = self.chunks[n].get_or_insert_with(|| Arc::new(vec![None; CHUNK_SIZE]));
+        Arc::make_mut(chunk)[pc % CHUNK_SIZE] = Some(instruction);
+    }
+
+    // The address was written to: forget the instructions which overlap it.
+    pub(crate) fn invalidate(&mut self, addr: Address) {
+        let first = addr.saturating_sub(MAX_INSTRUCTION_LEN - 1);
+
+        for start in first..=addr {
+            let overlaps = self
+                .get(start)
+                .is_some_and(|instr| start + instr.len() > addr);
+
+            if overlaps {
+                if let Some(Some(chunk)) = self.chunks.get_mut(start / CHUNK_SIZE) {
+                    Arc::make_mut(chunk)[start % CHUNK_SIZE] = None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{assemble, ExecutionOption, Memory, Outcome, State, VM};
+
+    ide!();
+
+    #[test]
+    fn self_modifying_code() {
+        // the loop patches its own add into a mul, after its first iteration
+        let program = assemble::<i64>(
+            "
+            loop:   add [x], [x], [x]
+                    out [x]
+                    add #2, #0, [loop]
+                    jt [more], #again
+                    hlt
+            again:  add #0, #0, [more]
+                    jt #1, #loop
+            x:      data 3
+            more:   data 1
+            ",
+        )
+        .unwrap();
+
+        let mut vm = VM::new(program);
+        assert_eq!(vm.run(), Ok(State::Output(6)));
+        assert_eq!(vm.run(), Ok(State::Output(36)));
+        assert_eq!(vm.run(), Ok(State::Halted));
+    }
+
+    #[test]
+    fn external_writes() {
+        // outputs [x]
+        let mut vm = VM::<i64>::new(vec![4, 3, 99, 5]);
+        vm.run().unwrap();
+
+        // patch the parameter of the (cached) output, and run it again
+        vm.tape.write(1, 0).unwrap();
+        vm.pc = 0;
+        assert_eq!(vm.run(), Ok(State::Output(4)));
+
+        // swap out the memory completely
+        vm.tape = Memory::new(vec![104, 7, 99]);
+        vm.pc = 0;
+        assert_eq!(vm.run(), Ok(State::Output(7)));
+    }
+
+    #[test]
+    fn forks() {
+        let vm = VM::<i64>::new(vec![4, 3, 99, 5]);
+
+        let mut fork = vm.fork();
+        fork.tape.write(1, 2).unwrap();
+        assert_eq!(
+            fork.execute(ExecutionOption::OutputByTapeOutput),
+            Ok(Outcome::Word(99))
+        );
+
+        // the parent still has its own instructions
+        let mut vm = vm;
+        assert_eq!(
+            vm.execute(ExecutionOption::OutputByTapeOutput),
+            Ok(Outcome::Word(5))
+        );
+    }
+
+    #[test]
+    fn invalidates_overlapping() {
+        let mut cache = InstructionCache::<i64>::new();
+        let tape = Memory::new(vec![1101, 1, 2, 0, 104, 5, 99]);
+
+        for pc in [0, 4, 6].iter() {
+            cache.insert(*pc, Instruction::fetch(&tape, *pc).unwrap());
+        }
+
+        // a write to the last parameter of the add only invalidates the add
+        cache.invalidate(3);
+        assert!(cache.get(0).is_none());
+        assert!(cache.get(4).is_some());
+        assert!(cache.get(6).is_some());
+
+        cache.invalidate(5);
+        assert!(cache.get(4).is_none());
+        assert!(cache.get(6).is_some());
+    }
+}
@@ -0,0 +1,233 @@
+use crate::vm::{State, VmError, Word, VM};
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, Write};
+
+// A piece of output of an ASCII program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decoded<W: Word> {
+    // consecutive ASCII characters
+    Text(String),
+    // a word which isn't an ASCII character, e.g. an answer too large for a character
+    Value(W),
+}
+
+// The input words of the characters of the text.
+pub fn encode<W: Word>(text: &str) -> Vec<W> {
+    text.chars().filter_map(|c| W::from_u32(c as u32)).collect()
+}
+
+// Output words as text, where possible.
+pub fn decode<W: Word, I: IntoIterator<Item = W>>(words: I) -> Vec<Decoded<W>> {
+    let mut decoded = Vec::new();
+
+    for word in words {
+        match (word.to_u8().filter(u8::is_ascii), decoded.last_mut()) {
+            (Some(c), Some(Decoded::Text(text))) => text.push(c as char),
+            (Some(c), _) => decoded.push(Decoded::Text((c as char).to_string())),
+            (None, _) => decoded.push(Decoded::Value(word)),
+        }
+    }
+
+    decoded
+}
+
+// What an ASCII program printed, until it wanted input or halted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply<W: Word> {
+    pub output: Vec<Decoded<W>>,
+    pub halted: bool,
+}
+
+impl<W: Word> Reply<W> {
+    // The values which aren't characters.
+    pub fn values(&self) -> impl Iterator<Item = &W> {
+        self.output.iter().filter_map(|decoded| match decoded {
+            Decoded::Value(value) => Some(value),
+            Decoded::Text(_) => None,
+        })
+    }
+}
+
+// Text as is; values as numbers, on a line of their own.
+impl<W: Word> Display for Reply<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for decoded in &self.output {
+            match decoded {
+                Decoded::Text(text) => write!(f, "{}", text)?,
+                Decoded::Value(value) => writeln!(f, "{}", value)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Talks to an Intcode program which communicates in ASCII.
+pub struct Console<W: Word> {
+    vm: VM<W>,
+}
+
+impl<W: Word> Console<W> {
+    pub fn new(vm: VM<W>) -> Self {
+        Self { vm }
+    }
+
+    pub fn vm(&self) -> &VM<W> {
+        &self.vm
+    }
+
+    pub fn into_vm(self) -> VM<W> {
+        self.vm
+    }
+
+    // Queue the text as input.
+    pub fn send(&mut self, text: &str) {
+        self.vm.inputs.extend(encode::<W>(text));
+    }
+
+    // Queue the line as input, followed by a newline.
+    pub fn send_line(&mut self, line: &str) {
+        self.send(line);
+        self.send("\n");
+    }
+
+    // Run until the program wants more input, or halts.
+    pub fn read(&mut self) -> Result<Reply<W>, VmError<W>> {
+        let halted = loop {
+            match self.vm.run()? {
+                State::NeedsInput => break false,
+                State::Halted => break true,
+                _ => continue,
+            }
+        };
+
+        Ok(Reply {
+            output: decode(self.vm.outputs.drain(..)),
+            halted,
+        })
+    }
+
+    // Let a human play: print what the program says, and send it each line which is read, until
+    // the program halts or the input ends. Errors of the program are printed too.
+    pub fn play<R: BufRead, O: Write>(&mut self, input: R, mut output: O) -> std::io::Result<()> {
+        let mut lines = input.lines();
+
+        loop {
+            match self.read() {
+                Ok(reply) => {
+                    write!(output, "{}", reply)?;
+                    output.flush()?;
+
+                    if reply.halted {
+                        return Ok(());
+                    }
+                }
+                Err(error) => return writeln!(output, "error: {}", error),
+            }
+
+            match lines.next() {
+                Some(line) => self.send_line(line?.trim_end_matches('\r')),
+                None => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::assemble;
+
+    ide!();
+
+    // prints a prompt, and echoes lines in upper case, until it reads an empty line; then
+    // prints 1000 plus the number of lines it read, which isn't a character
+    fn shout() -> Vec<i64> {
+        assemble(
+            "
+            prompt: out #62
+                    out #32
+            loop:   in [c]
+                    eq [c], #10, [nl]
+                    jt [nl], #eol
+                    add #0, #0, [empty]
+                    lt [c], #97, [lower]
+                    jt [lower], #print
+                    add [c], #-32, [c]
+            print:  out [c]
+                    jt #1, #loop
+            eol:    jt [empty], #done
+                    out #10
+                    add [n], #1, [n]
+                    add #1, #0, [empty]
+                    jt #1, #prompt
+            done:   add [n], #1000, [n]
+                    out [n]
+                    hlt
+            c:      data 0
+            nl:     data 0
+            lower:  data 0
+            empty:  data 1
+            n:      data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn codec() {
+        let words = encode::<i64>("Hi\n");
+        assert_eq!(words, vec![72, 105, 10]);
+
+        let decoded = decode(vec![72, 105, 10, 1234, 33, -1]);
+        assert_eq!(
+            decoded,
+            vec![
+                Decoded::Text("Hi\n".to_string()),
+                Decoded::Value(1234),
+                Decoded::Text("!".to_string()),
+                Decoded::Value(-1),
+            ]
+        );
+    }
+
+    #[test]
+    fn conversation() {
+        let mut console = Console::new(VM::new(shout()));
+
+        let reply = console.read().unwrap();
+        assert_eq!(reply.to_string(), "> ");
+        assert!(!reply.halted);
+
+        console.send_line("hello");
+        assert_eq!(console.read().unwrap().to_string(), "HELLO\n> ");
+
+        console.send_line("");
+        let reply = console.read().unwrap();
+        assert!(reply.halted);
+        assert_eq!(reply.values().collect::<Vec<_>>(), vec![&1001]);
+        assert_eq!(reply.to_string(), "1001\n");
+    }
+
+    #[test]
+    fn play() {
+        let mut console = Console::new(VM::new(shout()));
+        let mut output = Vec::new();
+
+        console
+            .play("abc\nd\n\nignored\n".as_bytes(), &mut output)
+            .unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "> ABC\n> D\n> 1002\n");
+    }
+
+    #[test]
+    fn play_until_input_ends() {
+        let mut console = Console::new(VM::new(shout()));
+        let mut output = Vec::new();
+
+        console.play("abc\n".as_bytes(), &mut output).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "> ABC\n> ");
+    }
+}
@@ -0,0 +1,502 @@
+use crate::vm::asm::opcode_of;
+use crate::vm::{Address, Instruction, Observer, Opcode, State, VmError, Word, VM};
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+const HELP: &str = "\
+commands:
+  s, step [n]             execute n instructions (default 1)
+  c, continue             run until a breakpoint, a watchpoint, input is needed, or halt
+  b, break <addr>         break before executing the instruction at addr
+  b, break op <op>        break before executing any instruction with the opcode (or mnemonic)
+  d, delete <addr>|op <op>  remove a breakpoint
+  w, watch <addr>         break after a write to addr
+  unwatch <addr>          remove a watchpoint
+  x <addr> [n]            show n words of memory, starting at addr (default 1)
+  set <addr> <value>      write value to memory at addr
+  l, list [addr] [n]      disassemble n instructions from addr (default: pc, 5)
+  i, input <values..>     queue input values
+  o, outputs              show the output queue, in emission order
+  r, regs                 show pc, relative base and queued inputs
+  h, help                 show this help
+  q, quit                 stop debugging";
+
+// At most this many words, or instructions, are shown at once.
+const MAX_SHOWN: usize = 4096;
+
+// Collects the memory writes of the VM, so watchpoints can be checked after each instruction.
+// Everything is passed on to the observer which the VM had before, if any.
+struct WriteLog<W: Word> {
+    writes: Vec<(Address, W)>,
+    observer: Option<Box<dyn Observer<W> + Send>>,
+}
+
+impl<W: Word> Observer<W> for WriteLog<W> {
+    fn instruction(&mut self, pc: Address, instruction: &Instruction<W>, params: &[Option<W>]) {
+        if let Some(observer) = &mut self.observer {
+            observer.instruction(pc, instruction, params);
+        }
+    }
+
+    fn write(&mut self, address: Address, value: &W) {
+        self.writes.push((address, value.clone()));
+
+        if let Some(observer) = &mut self.observer {
+            observer.write(address, value);
+        }
+    }
+}
+
+// Why the debugger stopped executing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Stop<W: Word> {
+    // executed all requested steps
+    Stepped,
+    Breakpoint(Address),
+    Watchpoint(Address, W),
+    NeedsInput,
+    Halted,
+    Error(VmError<W>),
+}
+
+// Runs a VM under control of text commands, see `HELP`.
+pub struct Debugger<W: Word> {
+    vm: VM<W>,
+    breakpoints: BTreeSet<Address>,
+    opcode_breakpoints: BTreeSet<Opcode>,
+    watchpoints: BTreeSet<Address>,
+    writes: Arc<Mutex<WriteLog<W>>>,
+}
+
+impl<W: Word> Debugger<W> {
+    // An observer of the VM keeps observing it, e.g. a `TraceWriter` traces the debugged program.
+    pub fn new(mut vm: VM<W>) -> Self {
+        let writes = Arc::new(Mutex::new(WriteLog {
+            writes: Vec::new(),
+            observer: vm.take_observer(),
+        }));
+
+        Self {
+            vm: vm.with_observer(writes.clone()),
+            breakpoints: BTreeSet::new(),
+            opcode_breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            writes,
+        }
+    }
+
+    pub fn vm(&self) -> &VM<W> {
+        &self.vm
+    }
+
+    // Read commands until `quit`, or the end of the input.
+    pub fn repl<R: BufRead, O: Write>(&mut self, input: R, mut output: O) -> std::io::Result<()> {
+        writeln!(output, "{}", self.location())?;
+        write!(output, "(icd) ")?;
+        output.flush()?;
+
+        for line in input.lines() {
+            match self.command(&line?) {
+                Some(reply) if reply.is_empty() => {}
+                Some(reply) => writeln!(output, "{}", reply)?,
+                None => return Ok(()),
+            }
+
+            write!(output, "(icd) ")?;
+            output.flush()?;
+        }
+
+        writeln!(output)
+    }
+
+    // Execute a single command, and describe the result. Returns `None` to quit.
+    pub fn command(&mut self, line: &str) -> Option<String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let reply = match words.as_slice() {
+            [] => Ok(String::new()),
+            ["q"] | ["quit"] => return None,
+            ["h"] | ["help"] => Ok(HELP.to_string()),
+            ["s"] | ["step"] => Ok(self.step(1)),
+            ["s", n] | ["step", n] => parse(n).map(|n| self.step(n)),
+            ["c"] | ["continue"] => Ok(self.resume()),
+            ["b", "op", op] | ["break", "op", op] => opcode(op).map(|op| {
+                self.opcode_breakpoints.insert(op);
+                format!("Breakpoint on opcode {}.", op)
+            }),
+            ["b", addr] | ["break", addr] => parse(addr).map(|addr| {
+                self.breakpoints.insert(addr);
+                format!("Breakpoint at {}.", addr)
+            }),
+            ["d", "op", op] | ["delete", "op", op] => opcode(op).map(|op| {
+                self.opcode_breakpoints.remove(&op);
+                format!("Removed breakpoint on opcode {}.", op)
+            }),
+            ["d", addr] | ["delete", addr] => parse(addr).map(|addr| {
+                self.breakpoints.remove(&addr);
+                format!("Removed breakpoint at {}.", addr)
+            }),
+            ["w", addr] | ["watch", addr] => parse(addr).map(|addr| {
+                self.watchpoints.insert(addr);
+                format!("Watching {}.", addr)
+            }),
+            ["unwatch", addr] => parse(addr).map(|addr| {
+                self.watchpoints.remove(&addr);
+                format!("Stopped watching {}.", addr)
+            }),
+            ["x", addr] => parse(addr).and_then(|addr| self.memory(addr, 1)),
+            ["x", addr, n] => parse(addr).and_then(|addr| self.memory(addr, count(n)?)),
+            ["set", addr, value] => parse(addr).and_then(|addr| {
+                let value: W = parse(value)?;
+                self.vm
+                    .tape
+                    .write(addr, value.clone())
+                    .map_err(|_| format!("Address {} is out of bounds.", addr))?;
+                Ok(format!("[{}] <- {}", addr, value))
+            }),
+            ["l"] | ["list"] => Ok(self.list(self.vm.pc, 5)),
+            ["l", addr] | ["list", addr] => parse(addr).map(|addr| self.list(addr, 5)),
+            ["l", addr, n] | ["list", addr, n] => {
+                parse(addr).and_then(|addr| Ok(self.list(addr, count(n)?)))
+            }
+            ["i", values @ ..] | ["input", values @ ..] => values
+                .iter()
+                .map(|value| parse(value))
+                .collect::<Result<Vec<W>, _>>()
+                .map(|values| {
+                    values.into_iter().for_each(|v| self.vm.push_input(v));
+                    format!("{} input(s) queued.", self.vm.inputs.len())
+                }),
+            ["o"] | ["outputs"] => Ok(format!("outputs: {}", join(self.vm.outputs.iter()))),
+            ["r"] | ["regs"] => Ok(format!(
+                "pc: {}, relative base: {}, inputs: {}",
+                self.vm.pc,
+                self.vm.relative_base,
+                join(self.vm.inputs.iter())
+            )),
+            _ => Err(format!("Unknown command '{}', try 'help'.", line.trim())),
+        };
+
+        Some(reply.unwrap_or_else(|error| error))
+    }
+
+    fn step(&mut self, n: usize) -> String {
+        let stop = (0..n)
+            .find_map(|_| self.execute_one())
+            .unwrap_or(Stop::Stepped);
+
+        self.describe(stop)
+    }
+
+    // Run until stopped, ignoring a breakpoint at the instruction we're resuming from.
+    fn resume(&mut self) -> String {
+        let mut stop = self.execute_one();
+
+        while stop.is_none() {
+            stop = self.breakpoint().or_else(|| self.execute_one());
+        }
+
+        self.describe(stop.unwrap_or(Stop::Stepped))
+    }
+
+    // Execute one instruction; returns why execution should stop, if it should.
+    fn execute_one(&mut self) -> Option<Stop<W>> {
+        let state = self.vm.step();
+        let writes = std::mem::take(&mut self.writes.lock().unwrap().writes);
+
+        let watched = writes
+            .into_iter()
+            .find(|(addr, _)| self.watchpoints.contains(addr));
+
+        match state {
+            Err(error) => Some(Stop::Error(error)),
+            Ok(State::NeedsInput) => Some(Stop::NeedsInput),
+            Ok(State::Halted) => Some(Stop::Halted),
+            Ok(_) => watched.map(|(addr, value)| Stop::Watchpoint(addr, value)),
+        }
+    }
+
+    fn breakpoint(&self) -> Option<Stop<W>> {
+        let pc = self.vm.pc;
+        let opcode = self
+            .vm
+            .tape
+            .read(pc)
+            .ok()
+            .and_then(|word| word.to_i32())
+            .map(|word| word % 100);
+
+        let hit = self.breakpoints.contains(&pc)
+            || opcode.is_some_and(|op| self.opcode_breakpoints.contains(&op));
+
+        if hit {
+            Some(Stop::Breakpoint(pc))
+        } else {
+            None
+        }
+    }
+
+    fn describe(&self, stop: Stop<W>) -> String {
+        let reason = match stop {
+            Stop::Stepped => String::new(),
+            Stop::Breakpoint(addr) => format!("Breakpoint at {}.\n", addr),
+            Stop::Watchpoint(addr, value) => format!("Watchpoint: [{}] <- {}\n", addr, value),
+            Stop::NeedsInput => "Waiting for input.\n".to_string(),
+            Stop::Halted => "Halted.\n".to_string(),
+            Stop::Error(error) => format!("Error: {}\n", error),
+        };
+
+        reason + &self.location()
+    }
+
+    // The instruction at the pc.
+    fn location(&self) -> String {
+        self.list(self.vm.pc, 1)
+    }
+
+    fn list(&self, mut addr: Address, n: usize) -> String {
+        let mut text = String::new();
+
+        for _ in 0..n {
+            let marker = if addr == self.vm.pc { "=>" } else { "  " };
+
+            let next = match Instruction::fetch(&self.vm.tape, addr) {
+                Ok(instr) => {
+                    writeln!(text, "{} {:>5}: {}", marker, addr, instr).unwrap();
+                    addr.checked_add(instr.len())
+                }
+                Err(_) => {
+                    let word = self.vm.tape.read(addr).unwrap_or_else(|_| W::zero());
+                    writeln!(text, "{} {:>5}: data {}", marker, addr, word).unwrap();
+                    addr.checked_add(1)
+                }
+            };
+
+            // the end of the address space
+            match next {
+                Some(next) => addr = next,
+                None => break,
+            }
+        }
+
+        text.trim_end().to_string()
+    }
+
+    fn memory(&self, addr: Address, n: usize) -> Result<String, String> {
+        let words = (addr..addr.saturating_add(n))
+            .map(|addr| self.vm.tape.read(addr))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| format!("Address {} is out of bounds.", error.0))?;
+
+        Ok(words
+            .chunks(8)
+            .enumerate()
+            .map(|(i, chunk)| format!("{:>5}: {}", addr + i * 8, join(chunk.iter())))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+fn parse<T: std::str::FromStr>(token: &str) -> Result<T, String> {
+    token
+        .parse()
+        .map_err(|_| format!("Invalid number '{}'.", token))
+}
+
+// A number of words or instructions to show.
+fn count(token: &str) -> Result<usize, String> {
+    match parse(token)? {
+        n if n > MAX_SHOWN => Err(format!("At most {} can be shown at once.", MAX_SHOWN)),
+        n => Ok(n),
+    }
+}
+
+// An opcode, by number or mnemonic.
+fn opcode(token: &str) -> Result<Opcode, String> {
+    opcode_of(token).map(Ok).unwrap_or_else(|| parse(token))
+}
+
+fn join<'a, W: Word>(words: impl Iterator<Item = &'a W>) -> String {
+    words.map(|w| w.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{assemble, Recorder, TraceEvent};
+    use parameterized::parameterized as pm;
+
+    ide!();
+
+    // doubles each input, until it reads a zero
+    const PROGRAM: &str = "
+        loop:   in [x]
+                jf [x], #end
+                mul [x], #2, [x]
+                out [x]
+                jt #1, #loop
+        end:    hlt
+        x:      data 0
+    ";
+
+    fn debugger(inputs: Vec<i64>) -> Debugger<i64> {
+        Debugger::new(VM::with_inputs(assemble(PROGRAM).unwrap(), inputs))
+    }
+
+    #[test]
+    fn keeps_observer() {
+        let recorder = Arc::new(Mutex::new(Recorder::new(10)));
+        let vm =
+            VM::with_inputs(assemble(PROGRAM).unwrap(), vec![3]).with_observer(recorder.clone());
+        let mut debugger = Debugger::new(vm);
+        run(&mut debugger, &["w 15", "c"]);
+
+        let recorder = recorder.lock().unwrap();
+        let events = recorder.events().collect::<Vec<_>>();
+
+        assert!(matches!(events[0], TraceEvent::Instruction { pc: 0, .. }));
+        assert_eq!(
+            events[1],
+            &TraceEvent::Write {
+                address: 15,
+                value: 3
+            }
+        );
+        assert_eq!(events.len(), 2);
+    }
+
+    fn run(debugger: &mut Debugger<i64>, commands: &[&str]) -> Vec<String> {
+        commands
+            .iter()
+            .map(|command| debugger.command(command).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn step() {
+        let mut debugger = debugger(vec![3]);
+        let replies = run(&mut debugger, &["step", "s 2", "regs"]);
+
+        assert_eq!(replies[0], "=>     2: jf [15], #14");
+        assert_eq!(replies[1], "=>     9: out [15]");
+        assert_eq!(replies[2], "pc: 9, relative base: 0, inputs: ");
+    }
+
+    #[pm(breakpoint = { "b 11", "break op out", "b op 4", "b op jt" }, pc = { 11, 9, 9, 11 }, outputs = {
+        "outputs: 6 8",
+        "outputs: 6",
+        "outputs: 6",
+        "outputs: 6 8",
+    })]
+    fn breakpoints(breakpoint: &str, pc: Address, outputs: &str) {
+        let mut debugger = debugger(vec![3, 4, 0]);
+        run(&mut debugger, &[breakpoint]);
+
+        assert!(debugger.command("c").unwrap().starts_with("Breakpoint at"));
+        assert_eq!(debugger.vm().pc, pc);
+
+        // continuing leaves the breakpoint, and stops at it in the next iteration
+        run(&mut debugger, &["c"]);
+        assert_eq!(debugger.vm().pc, pc);
+        assert_eq!(debugger.command("o").unwrap(), outputs);
+    }
+
+    #[test]
+    fn delete_breakpoint() {
+        let mut debugger = debugger(vec![3, 4, 0]);
+        let replies = run(&mut debugger, &["b 9", "d 9", "c"]);
+
+        assert_eq!(replies[2], "Halted.\n=>    14: hlt");
+        assert_eq!(debugger.command("outputs").unwrap(), "outputs: 6 8");
+    }
+
+    #[test]
+    fn watchpoint() {
+        let mut debugger = debugger(vec![3]);
+        let replies = run(&mut debugger, &["watch 15", "c", "c", "c"]);
+
+        assert_eq!(replies[1], "Watchpoint: [15] <- 3\n=>     2: jf [15], #14");
+        assert_eq!(replies[2], "Watchpoint: [15] <- 6\n=>     9: out [15]");
+        assert_eq!(replies[3], "Waiting for input.\n=>     0: in [15]");
+    }
+
+    // the jump isn't taken, so its invalid target is never read
+    #[test]
+    fn agrees_with_plain_run() {
+        let program = vec![2105, 0, -5, 99];
+        let mut plain = VM::new(program.clone());
+        plain.run().unwrap();
+
+        let mut debugger = Debugger::new(VM::new(program));
+        assert_eq!(debugger.command("c").unwrap(), "Halted.\n=>     3: hlt");
+        assert_eq!(debugger.vm().pc, plain.pc);
+    }
+
+    #[test]
+    fn memory() {
+        let mut debugger = debugger(vec![]);
+        let replies = run(
+            &mut debugger,
+            &[
+                "x 0 10",
+                "set 15 21",
+                "x 15",
+                "x 99999999999",
+                "x 0 99999999999",
+            ],
+        );
+
+        assert_eq!(replies[0], "    0: 3 15 1006 15 14 1002 15 2\n    8: 15 4");
+        assert_eq!(replies[1], "[15] <- 21");
+        assert_eq!(replies[2], "   15: 21");
+        assert_eq!(replies[3], "Address 99999999999 is out of bounds.");
+        assert_eq!(replies[4], "At most 4096 can be shown at once.");
+    }
+
+    #[test]
+    fn list_to_the_end_of_the_address_space() {
+        let mut debugger = debugger(vec![]);
+        let replies = run(&mut debugger, &["l 18446744073709551615 2", "l 0 5000"]);
+
+        assert_eq!(replies[0], "   18446744073709551615: data 0");
+        assert_eq!(replies[1], "At most 4096 can be shown at once.");
+    }
+
+    #[test]
+    fn input_and_list() {
+        let mut debugger = debugger(vec![]);
+        let replies = run(&mut debugger, &["c", "input 5 0", "c", "o", "l 13 2"]);
+
+        assert_eq!(replies[0], "Waiting for input.\n=>     0: in [15]");
+        assert_eq!(replies[1], "2 input(s) queued.");
+        assert_eq!(replies[2], "Halted.\n=>    14: hlt");
+        assert_eq!(replies[3], "outputs: 10");
+        assert_eq!(replies[4], "      13: data 0\n=>    14: hlt");
+    }
+
+    #[pm(command = { "jump", "b x", "step -1", "input 1 a" }, expected = {
+        "Unknown command 'jump', try 'help'.",
+        "Invalid number 'x'.",
+        "Invalid number '-1'.",
+        "Invalid number 'a'.",
+    })]
+    fn bad_commands(command: &str, expected: &str) {
+        assert_eq!(debugger(vec![]).command(command).unwrap(), expected);
+    }
+
+    #[test]
+    fn repl() {
+        let mut debugger = debugger(vec![1, 0]);
+        let mut output = Vec::new();
+        debugger
+            .repl("c\no\nq\nregs\n".as_bytes(), &mut output)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "=>     0: in [15]\n(icd) Halted.\n=>    14: hlt\n(icd) outputs: 2\n(icd) "
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use crate::vm::{Address, Instruction, Memory, Word};
+use std::fmt::Write;
+
+// At most this many words are put on a single `data` line.
+const DATA_PER_LINE: usize = 8;
+
+// Render a program as annotated assembly, one instruction per line:
+//
+//     0: add [9], #3, [9]         ; 1001,9,3,9
+//     4: out rb-1                 ; 204,-1
+//     6: hlt                      ; 99
+//     7: data 0, 0, 42
+//
+// The program is decoded with a linear sweep from address 0. Words which don't decode to an
+// instruction, or instructions which would run past the end of the program, are shown as data.
+// Since code and data are mixed freely, data may also happen to decode as an instruction.
+pub fn disassemble<W: Word>(program: &[W]) -> String {
+    // reading beyond the program must fail, instead of reading zeroes
+    let memory = Memory::new(program.to_vec()).with_limit(program.len());
+
+    let mut text = String::new();
+    let mut data: Vec<&W> = Vec::new();
+    let mut data_start = 0;
+    let mut pc = 0;
+
+    while pc < program.len() {
+        match Instruction::fetch(&memory, pc) {
+            Ok(instr) => {
+                flush_data(&mut text, data_start, &mut data);
+
+                let len = instr.len();
+                let raw = join(&program[pc..pc + len], ",");
+                writeln!(text, "{:>5}: {:<24} ; {}", pc, instr.to_string(), raw).unwrap();
+
+                pc += len;
+            }
+            Err(_) => {
+                if data.len() == DATA_PER_LINE {
+                    flush_data(&mut text, data_start, &mut data);
+                }
+                if data.is_empty() {
+                    data_start = pc;
+                }
+
+                data.push(&program[pc]);
+                pc += 1;
+            }
+        }
+    }
+
+    flush_data(&mut text, data_start, &mut data);
+    text
+}
+
+fn flush_data<W: Word>(text: &mut String, start: Address, data: &mut Vec<&W>) {
+    if !data.is_empty() {
+        writeln!(text, "{:>5}: data {}", start, join(data.iter(), ", ")).unwrap();
+        data.clear();
+    }
+}
+
+fn join<T: std::fmt::Display>(words: impl IntoIterator<Item = T>, sep: &str) -> String {
+    words
+        .into_iter()
+        .map(|w| w.to_string())
+        .collect::<Vec<_>>()
+        .join(sep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parameterized::parameterized as pm;
+
+    ide!();
+
+    fn code(addr: Address, asm: &str, raw: &str) -> String {
+        format!("{:>5}: {:<24} ; {}\n", addr, asm, raw)
+    }
+
+    #[pm(program = {
+        &[1001, 9, 3, 9],
+        &[2, 0, 0, 0],
+        &[3, 5],
+        &[203, -2],
+        &[4, 7],
+        &[104, -7],
+        &[1105, 1, 12],
+        &[1006, 5, 0],
+        &[6, 4, -5],
+        &[21107, 1, 2, 3],
+        &[1208, 0, 3, 5],
+        &[109, 19],
+        &[99],
+    }, expected = {
+        "add [9], #3, [9]",
+        "mul [0], [0], [0]",
+        "in [5]",
+        "in rb-2",
+        "out [7]",
+        "out #-7",
+        "jt #1, #12",
+        "jf [5], #0",
+        // the address is only checked when it's read
+        "jf [4], [-5]",
+        "lt #1, #2, rb+3",
+        "eq rb+0, #3, [5]",
+        "arb #19",
+        "hlt",
+    })]
+    fn instructions(program: &[i64], expected: &str) {
+        assert_eq!(disassemble(program), code(0, expected, &join(program, ",")));
+    }
+
+    #[test]
+    fn program() {
+        let program = [1, 9, 10, 11, 4, 11, 99, 0, 0, 30, 40, 50];
+
+        assert_eq!(
+            disassemble(&program),
+            [
+                code(0, "add [9], [10], [11]", "1,9,10,11"),
+                code(4, "out [11]", "4,11"),
+                code(6, "hlt", "99"),
+                "    7: data 0, 0, 30, 40, 50\n".to_string(),
+            ]
+            .concat()
+        );
+    }
+
+    #[pm(program = {
+        &[99, 0, 123, -4, 0],
+        &[99, 1, 9],
+        &[99, 1101, 1, 2, 3, 99],
+        &[99, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    }, expected = {
+        vec!["    1: data 0, 123, -4, 0\n".to_string()],
+        // the add would run past the end of the program
+        vec!["    1: data 1, 9\n".to_string()],
+        // data decoding as an instruction
+        vec![code(1, "add #1, #2, [3]", "1101,1,2,3"), code(5, "hlt", "99")],
+        vec![
+            "    1: data 0, 0, 0, 0, 0, 0, 0, 0\n".to_string(),
+            "    9: data 0\n".to_string(),
+        ],
+    })]
+    fn data(program: &[i64], expected: Vec<String>) {
+        assert_eq!(
+            disassemble(program),
+            code(0, "hlt", "99") + &expected.concat()
+        );
+    }
+
+    #[test]
+    fn immediate_write_is_data() {
+        assert_eq!(
+            disassemble(&[11101, 1, 2, 3]),
+            "    0: data 11101, 1, 2, 3\n"
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use crate::vm::{Address, Opcode, Word};
+use std::fmt::{Display, Formatter};
+
+// Reasons for the VM to stop early. Each carries the program counter of the faulting instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError<W: Word> {
+    // the word at pc is not a known instruction
+    UnknownOpcode {
+        pc: Address,
+        opcode: W,
+    },
+    // a parameter mode digit of the instruction is not 0, 1 or 2
+    BadParameterMode {
+        pc: Address,
+        opcode: Opcode,
+        mode: Opcode,
+    },
+    // the address is beyond the limit of the memory
+    OutOfBounds {
+        pc: Address,
+        address: Address,
+    },
+    // the word can't be used as address; most likely it's negative
+    NegativeAddress {
+        pc: Address,
+        value: W,
+    },
+    // an input instruction was reached, but no input was available
+    InputExhausted {
+        pc: Address,
+    },
+    // a parameter which is written to was in immediate mode
+    WriteInImmediateMode {
+        pc: Address,
+    },
+    // the result of an instruction doesn't fit in a word
+    Overflow {
+        pc: Address,
+    },
+    // the program halted without producing an output, while one was requested
+    NoOutput {
+        pc: Address,
+    },
+    // the VM executed as many instructions as its step limit allows
+    StepLimitExceeded {
+        pc: Address,
+        limit: u64,
+    },
+    // the jump at pc returned the VM to a state it was in before, so it will never halt
+    InfiniteLoop {
+        pc: Address,
+    },
+}
+
+impl<W: Word> VmError<W> {
+    pub fn pc(&self) -> Address {
+        match self {
+            VmError::UnknownOpcode { pc, .. }
+            | VmError::BadParameterMode { pc, .. }
+            | VmError::OutOfBounds { pc, .. }
+            | VmError::NegativeAddress { pc, .. }
+            | VmError::InputExhausted { pc }
+            | VmError::WriteInImmediateMode { pc }
+            | VmError::Overflow { pc }
+            | VmError::NoOutput { pc }
+            | VmError::StepLimitExceeded { pc, .. }
+            | VmError::InfiniteLoop { pc } => *pc,
+        }
+    }
+}
+
+impl<W: Word> Display for VmError<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmError::UnknownOpcode { pc, opcode } => {
+                write!(f, "Unknown opcode {} at address {}.", opcode, pc)
+            }
+            VmError::BadParameterMode { pc, opcode, mode } => write!(
+                f,
+                "Bad parameter mode {} in opcode {} at address {}.",
+                mode, opcode, pc
+            ),
+            VmError::OutOfBounds { pc, address } => write!(
+                f,
+                "Memory access out of bounds at address {} (pc: {}).",
+                address, pc
+            ),
+            VmError::NegativeAddress { pc, value } => {
+                write!(f, "Invalid address {} (pc: {}).", value, pc)
+            }
+            VmError::InputExhausted { pc } => {
+                write!(f, "Input requested but none available (pc: {}).", pc)
+            }
+            VmError::WriteInImmediateMode { pc } => {
+                write!(f, "Write parameter in immediate mode (pc: {}).", pc)
+            }
+            VmError::Overflow { pc } => write!(f, "Word overflowed (pc: {}).", pc),
+            VmError::NoOutput { pc } => {
+                write!(f, "Program halted without output (pc: {}).", pc)
+            }
+            VmError::StepLimitExceeded { pc, limit } => write!(
+                f,
+                "Step limit of {} instructions exceeded (pc: {}).",
+                limit, pc
+            ),
+            VmError::InfiniteLoop { pc } => write!(f, "Infinite loop detected (pc: {}).", pc),
+        }
+    }
+}
+
+impl<W: Word> std::error::Error for VmError<W> {}
@@ -0,0 +1,469 @@
+use crate::vm::{to_address, Address, InParam, Instruction, Memory, VmError, Word};
+use petgraph::dot::Dot;
+use petgraph::graph::NodeIndex;
+use petgraph::Graph;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+// A straight run of instructions, which is only entered at its first instruction, and only left
+// after its last one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block<W: Word> {
+    // the instructions with their addresses, in order; never empty
+    pub instructions: Vec<(Address, Instruction<W>)>,
+}
+
+impl<W: Word> Block<W> {
+    pub fn start(&self) -> Address {
+        self.instructions[0].0
+    }
+
+    // The address after the last instruction.
+    pub fn end(&self) -> Address {
+        let (pc, instruction) = &self.instructions[self.instructions.len() - 1];
+        pc + instruction.len()
+    }
+}
+
+// One instruction per line, e.g. `5: out [15]`. Indirect jumps are marked.
+impl<W: Word> Display for Block<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (pc, instruction) in &self.instructions {
+            write!(f, "{}: {}", pc, instruction)?;
+
+            let (exits, _) = exits(*pc, instruction);
+            if exits.jump == Some(Jump::Indirect) {
+                write!(f, " ; indirect")?;
+            }
+
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+// How control passes from one block to the next.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Flow {
+    // to the instruction right after the block
+    Next,
+    // by a jump to an immediate address
+    Jump,
+}
+
+impl Display for Flow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Flow::Next => write!(f, "next"),
+            Flow::Jump => write!(f, "jump"),
+        }
+    }
+}
+
+// The control flow graph of a program, found by `analyze`.
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph<W: Word> {
+    graph: Graph<Block<W>, Flow>,
+    // the nodes of the blocks, by their start
+    blocks: BTreeMap<Address, NodeIndex>,
+    indirect_jumps: Vec<Address>,
+    unreachable: Vec<Range<Address>>,
+    errors: BTreeMap<Address, VmError<W>>,
+}
+
+impl<W: Word> ControlFlowGraph<W> {
+    pub fn graph(&self) -> &Graph<Block<W>, Flow> {
+        &self.graph
+    }
+
+    // The block at address 0, unless the program can't start at all.
+    pub fn entry(&self) -> Option<NodeIndex> {
+        self.blocks.get(&0).cloned()
+    }
+
+    // The block which starts at the address.
+    pub fn block(&self, start: Address) -> Option<&Block<W>> {
+        self.blocks.get(&start).map(|&node| &self.graph[node])
+    }
+
+    // The addresses of reachable jumps, whose target is only known at runtime. Where they jump to
+    // isn't part of the graph.
+    pub fn indirect_jumps(&self) -> &[Address] {
+        &self.indirect_jumps
+    }
+
+    // The parts of the program which no instruction reachable from address 0 occupies: data, or
+    // code which is only reached through indirect jumps.
+    pub fn unreachable(&self) -> &[Range<Address>] {
+        &self.unreachable
+    }
+
+    // The reachable addresses which don't hold a valid instruction, or jump to an invalid address.
+    pub fn errors(&self) -> impl Iterator<Item = &VmError<W>> {
+        self.errors.values()
+    }
+
+    // The graph in the Graphviz DOT language, e.g. to render it with `dot -Tsvg`.
+    pub fn to_dot(&self) -> String {
+        Dot::new(&self.graph).to_string()
+    }
+}
+
+// Find the control flow graph of a program, without running it. The program is walked from
+// address 0, following each instruction to the next one, and each jump to an immediate address.
+// Jumps with an immediate condition are known to be either always or never taken.
+//
+// Since programs may modify themselves, this is only what the program looks like before it runs.
+pub fn analyze<W: Word>(program: &[W]) -> ControlFlowGraph<W> {
+    // reading beyond the program must fail, instead of reading zeroes
+    let memory = Memory::new(program.to_vec()).with_limit(program.len());
+
+    let mut decoded: BTreeMap<Address, (Instruction<W>, Exits)> = BTreeMap::new();
+    let mut errors = BTreeMap::new();
+    let mut indirect_jumps = Vec::new();
+    // the addresses at which a block starts
+    let mut leaders = BTreeSet::new();
+    let mut pending = vec![0];
+
+    leaders.insert(0);
+
+    while let Some(pc) = pending.pop() {
+        if decoded.contains_key(&pc) || errors.contains_key(&pc) {
+            continue;
+        }
+
+        let instruction = match Instruction::fetch(&memory, pc) {
+            Ok(instruction) => instruction,
+            Err(error) => {
+                errors.insert(pc, error);
+                continue;
+            }
+        };
+
+        // a jump to an invalid address is an error, but it may not be taken
+        let (exits, error) = exits(pc, &instruction);
+        if let Some(error) = error {
+            errors.insert(pc, error);
+        }
+
+        let next = pc + instruction.len();
+
+        if exits.next {
+            pending.push(next);
+        }
+
+        match exits.jump {
+            Some(Jump::To(target)) => {
+                leaders.insert(target);
+                pending.push(target);
+            }
+            Some(Jump::Indirect) => indirect_jumps.push(pc),
+            Some(Jump::Invalid) | None => {}
+        }
+
+        // a jump ends its block, even when it's not always taken
+        if exits.jump.is_some() {
+            leaders.insert(next);
+        }
+
+        decoded.insert(pc, (instruction, exits));
+    }
+
+    let mut graph = Graph::new();
+    let mut blocks = BTreeMap::new();
+
+    for &leader in &leaders {
+        let mut instructions = Vec::new();
+        let mut pc = leader;
+
+        while let Some((instruction, exits)) = decoded.get(&pc) {
+            instructions.push((pc, instruction.clone()));
+            pc += instruction.len();
+
+            if !exits.next || exits.jump.is_some() || leaders.contains(&pc) {
+                break;
+            }
+        }
+
+        if !instructions.is_empty() {
+            blocks.insert(leader, graph.add_node(Block { instructions }));
+        }
+    }
+
+    for &node in blocks.values() {
+        let block: &Block<W> = &graph[node];
+        let (last, _) = &block.instructions[block.instructions.len() - 1];
+        let (_, exits) = &decoded[last];
+        let next = block.end();
+
+        if let Some(&to) = blocks.get(&next).filter(|_| exits.next) {
+            graph.add_edge(node, to, Flow::Next);
+        }
+
+        if let Some(Jump::To(target)) = exits.jump {
+            if let Some(&to) = blocks.get(&target) {
+                graph.add_edge(node, to, Flow::Jump);
+            }
+        }
+    }
+
+    // invalid instructions are reached, even if they don't run
+    let mut reached = vec![false; program.len()];
+    for (pc, (instruction, _)) in &decoded {
+        reached[*pc..pc + instruction.len()]
+            .iter_mut()
+            .for_each(|reached| *reached = true);
+    }
+    for pc in errors.keys().filter(|&&pc| pc < program.len()) {
+        reached[*pc] = true;
+    }
+
+    let mut unreachable: Vec<Range<Address>> = Vec::new();
+    for address in (0..program.len()).filter(|&address| !reached[address]) {
+        match unreachable.last_mut() {
+            Some(range) if range.end == address => range.end += 1,
+            _ => unreachable.push(address..address + 1),
+        }
+    }
+
+    indirect_jumps.sort_unstable();
+
+    ControlFlowGraph {
+        graph,
+        blocks,
+        indirect_jumps,
+        unreachable,
+        errors,
+    }
+}
+
+// Where control may go after an instruction, as far as can be told without running it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Exits {
+    // to the next instruction
+    next: bool,
+    jump: Option<Jump>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Jump {
+    To(Address),
+    // to an address which is read at runtime
+    Indirect,
+    // to an immediate address which isn't valid, so the VM fails if it's taken
+    Invalid,
+}
+
+// The exits of the instruction at `pc`, with the error of a jump to an invalid address, if any.
+fn exits<W: Word>(pc: Address, instruction: &Instruction<W>) -> (Exits, Option<VmError<W>>) {
+    let (jumps_if_zero, [condition, target]) = match instruction {
+        Instruction::JumpIfTrue(params) => (false, params),
+        Instruction::JumpIfFalse(params) => (true, params),
+        Instruction::Ret => {
+            let exits = Exits {
+                next: false,
+                jump: None,
+            };
+            return (exits, None);
+        }
+        _ => {
+            let exits = Exits {
+                next: true,
+                jump: None,
+            };
+            return (exits, None);
+        }
+    };
+
+    let (taken, not_taken) = match condition {
+        InParam::Immediate(condition) => {
+            let taken = condition.is_zero() == jumps_if_zero;
+            (taken, !taken)
+        }
+        _ => (true, true),
+    };
+
+    let (jump, error) = match target {
+        _ if !taken => (None, None),
+        InParam::Immediate(target) => match to_address(target, pc) {
+            Ok(target) => (Some(Jump::To(target)), None),
+            Err(error) => (Some(Jump::Invalid), Some(error)),
+        },
+        _ => (Some(Jump::Indirect), None),
+    };
+
+    let exits = Exits {
+        next: not_taken,
+        jump,
+    };
+    (exits, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::assemble;
+    use parameterized::parameterized as pm;
+
+    ide!();
+
+    // counts down from the input, outputting each number
+    fn countdown() -> Vec<i64> {
+        assemble(
+            "
+                    in [n]
+            loop:   jf [n], #done
+                    out [n]
+                    add [n], #-1, [n]
+                    jt #1, #loop
+            done:   hlt
+            n:      data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    // (from, to, flow) by the start of the blocks
+    fn edges<W: Word>(cfg: &ControlFlowGraph<W>) -> Vec<(Address, Address, Flow)> {
+        let graph = cfg.graph();
+        let mut edges = graph
+            .raw_edges()
+            .iter()
+            .map(|edge| {
+                (
+                    graph[edge.source()].start(),
+                    graph[edge.target()].start(),
+                    edge.weight,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        edges.sort_by_key(|&(from, to, _)| (from, to));
+        edges
+    }
+
+    // (start, end) of the unreachable regions
+    fn unreachable<W: Word>(cfg: &ControlFlowGraph<W>) -> Vec<(Address, Address)> {
+        cfg.unreachable()
+            .iter()
+            .map(|range| (range.start, range.end))
+            .collect()
+    }
+
+    #[test]
+    fn blocks() {
+        let cfg = analyze(&countdown());
+
+        let starts = cfg
+            .graph()
+            .raw_nodes()
+            .iter()
+            .map(|node| (node.weight.start(), node.weight.end()))
+            .collect::<Vec<_>>();
+        assert_eq!(starts, vec![(0, 2), (2, 5), (5, 14), (14, 15)]);
+
+        assert_eq!(
+            edges(&cfg),
+            vec![
+                (0, 2, Flow::Next),
+                (2, 5, Flow::Next),
+                (2, 14, Flow::Jump),
+                (5, 2, Flow::Jump),
+            ]
+        );
+
+        assert_eq!(cfg.entry().map(|entry| cfg.graph()[entry].start()), Some(0));
+        assert_eq!(unreachable(&cfg), vec![(15, 16)]);
+        assert!(cfg.indirect_jumps().is_empty());
+        assert_eq!(cfg.errors().count(), 0);
+    }
+
+    // a jump which is never taken doesn't end its block
+    #[pm(source = {
+        "jt #1, #end\nout #1\nend: hlt",
+        "jf #1, #end\nout #1\nend: hlt",
+        "jt #0, #end\nout #1\nend: hlt",
+    }, expected_edges = {
+        vec![(0, 5, Flow::Jump)],
+        vec![],
+        vec![],
+    }, expected_unreachable = {
+        vec![(3, 5)],
+        vec![],
+        vec![],
+    })]
+    fn immediate_conditions(
+        source: &str,
+        expected_edges: Vec<(Address, Address, Flow)>,
+        expected_unreachable: Vec<(Address, Address)>,
+    ) {
+        let cfg = analyze(&assemble::<i64>(source).unwrap());
+
+        assert_eq!(edges(&cfg), expected_edges);
+        assert_eq!(unreachable(&cfg), expected_unreachable);
+    }
+
+    #[test]
+    fn indirect_jumps() {
+        let program = assemble::<i64>(
+            "
+                    jt [flag], [target]
+                    hlt
+                    out #1
+                    hlt
+            flag:   data 1
+            target: data 4
+            ",
+        )
+        .unwrap();
+        let cfg = analyze(&program);
+
+        assert_eq!(cfg.indirect_jumps(), &[0]);
+        assert_eq!(edges(&cfg), vec![(0, 3, Flow::Next)]);
+        // the target is only known at runtime
+        assert_eq!(unreachable(&cfg), vec![(4, 9)]);
+        assert_eq!(
+            cfg.block(0).unwrap().to_string(),
+            "0: jt [7], [8] ; indirect\n"
+        );
+    }
+
+    #[pm(program = {
+        vec![1, 0, 0, 0, 42],
+        vec![1105, 1, -5],
+        vec![1, 0, 0],
+    }, expected = {
+        VmError::UnknownOpcode { pc: 4, opcode: 42 },
+        VmError::NegativeAddress { pc: 0, value: -5 },
+        VmError::OutOfBounds { pc: 0, address: 3 },
+    })]
+    fn errors(program: Vec<i64>, expected: VmError<i64>) {
+        let cfg = analyze(&program);
+
+        assert_eq!(cfg.errors().collect::<Vec<_>>(), vec![&expected]);
+    }
+
+    #[test]
+    fn invalid_jump_may_not_be_taken() {
+        // jumps to -5 unless [9] is 0, which it is
+        let cfg = analyze(&[1005, 9, -5, 104, 1, 99, 0, 0, 0, 0]);
+
+        assert_eq!(
+            cfg.errors().collect::<Vec<_>>(),
+            vec![&VmError::NegativeAddress { pc: 0, value: -5 }]
+        );
+        assert_eq!(edges(&cfg), vec![(0, 3, Flow::Next)]);
+        assert_eq!(unreachable(&cfg), vec![(6, 10)]);
+    }
+
+    #[test]
+    fn dot() {
+        let dot = analyze(&countdown()).to_dot();
+
+        assert!(dot.starts_with("digraph {"));
+        assert!(dot.contains(r#"[label="5: out [15]\l7: add [15], #-1, [15]\l11: jt #1, #2\l"]"#));
+        assert!(dot.contains(r#"[label="jump"]"#));
+    }
+}
@@ -0,0 +1,329 @@
+use crate::vm::{Word, VM};
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{Receiver, Sender};
+
+// A source of inputs for a VM. Attach one with `VM::with_input`; it's read once the inputs
+// queued in `VM::inputs` are used up.
+pub trait Input<W: Word> {
+    // The next input, or `None` if there's none (yet); the VM then pauses with `NeedsInput`,
+    // and asks again when it's resumed.
+    fn read(&mut self) -> Option<W>;
+}
+
+// Receives the outputs of a VM, in emission order. Attach one with `VM::with_output`, instead
+// of collecting the outputs in `VM::outputs`.
+pub trait Output<W: Word> {
+    fn write(&mut self, value: W);
+}
+
+// in-memory buffers
+
+impl<W: Word> Input<W> for VecDeque<W> {
+    fn read(&mut self) -> Option<W> {
+        self.pop_front()
+    }
+}
+
+impl<W: Word> Output<W> for VecDeque<W> {
+    fn write(&mut self, value: W) {
+        self.push_back(value);
+    }
+}
+
+impl<W: Word> Output<W> for Vec<W> {
+    fn write(&mut self, value: W) {
+        self.push(value);
+    }
+}
+
+// closures
+
+impl<W: Word, F: FnMut() -> Option<W>> Input<W> for F {
+    fn read(&mut self) -> Option<W> {
+        self()
+    }
+}
+
+impl<W: Word, F: FnMut(W)> Output<W> for F {
+    fn write(&mut self, value: W) {
+        self(value)
+    }
+}
+
+// Inputs taken from an iterator, e.g. `IterInput::new(1..)`.
+#[derive(Debug, Clone)]
+pub struct IterInput<I>(I);
+
+impl<I> IterInput<I> {
+    pub fn new<T: IntoIterator<IntoIter = I>>(inputs: T) -> Self {
+        IterInput(inputs.into_iter())
+    }
+}
+
+impl<W: Word, I: Iterator<Item = W>> Input<W> for IterInput<I> {
+    fn read(&mut self) -> Option<W> {
+        self.0.next()
+    }
+}
+
+// channels
+
+// Blocks until an input is sent. Once all senders are gone, there are no more inputs.
+impl<W: Word> Input<W> for Receiver<W> {
+    fn read(&mut self) -> Option<W> {
+        self.recv().ok()
+    }
+}
+
+// Outputs are dropped once the receiver is gone.
+impl<W: Word> Output<W> for Sender<W> {
+    fn write(&mut self, value: W) {
+        let _ = self.send(value);
+    }
+}
+
+// How words are read and written as text.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextMode {
+    // a number per line
+    Numeric,
+    // a character per word; the characters of each line, including its newline
+    Ascii,
+}
+
+// Inputs read from text, e.g. `TextInput::new(BufReader::new(std::io::stdin()), TextMode::Ascii)`.
+//
+// Lines are read as they're needed. The inputs end at the end of the text, or at the first line
+// which isn't a number in numeric mode, or isn't ASCII in ASCII mode. Once ended, no more lines
+// are read.
+#[derive(Debug)]
+pub struct TextInput<R: BufRead, W: Word> {
+    reader: R,
+    mode: TextMode,
+    // the rest of the current line
+    pending: VecDeque<W>,
+    ended: bool,
+}
+
+impl<R: BufRead, W: Word> TextInput<R, W> {
+    pub fn new(reader: R, mode: TextMode) -> Self {
+        Self {
+            reader,
+            mode,
+            pending: VecDeque::new(),
+            ended: false,
+        }
+    }
+
+    // The words of the next line, if any.
+    fn read_line(&mut self) -> Option<VecDeque<W>> {
+        if self.ended {
+            return None;
+        }
+
+        let mut line = String::new();
+        let words = match self.reader.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => match self.mode {
+                TextMode::Numeric => line.trim().parse().ok().map(|w| vec![w].into()),
+                TextMode::Ascii if line.is_ascii() => line.bytes().map(W::from_u8).collect(),
+                TextMode::Ascii => None,
+            },
+        };
+
+        self.ended = words.is_none();
+        words
+    }
+}
+
+impl<R: BufRead, W: Word> Input<W> for TextInput<R, W> {
+    fn read(&mut self) -> Option<W> {
+        if self.pending.is_empty() {
+            self.pending = self.read_line()?;
+        }
+
+        self.pending.pop_front()
+    }
+}
+
+// Outputs written as text, e.g. `TextOutput::new(std::io::stdout(), TextMode::Numeric)`.
+//
+// In ASCII mode, words which aren't ASCII characters are written as numbers, on a line of their
+// own. Writing is best effort: errors are ignored.
+#[derive(Debug)]
+pub struct TextOutput<T: Write> {
+    out: T,
+    mode: TextMode,
+}
+
+impl<T: Write> TextOutput<T> {
+    pub fn new(out: T, mode: TextMode) -> Self {
+        Self { out, mode }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.out
+    }
+}
+
+impl<W: Word, T: Write> Output<W> for TextOutput<T> {
+    fn write(&mut self, value: W) {
+        let _ = match (self.mode, value.to_u8()) {
+            (TextMode::Ascii, Some(c)) if c.is_ascii() => self.out.write_all(&[c]),
+            _ => writeln!(self.out, "{}", value),
+        };
+        let _ = self.out.flush();
+    }
+}
+
+impl<W: Word> VM<W> {
+    // Read inputs from the source, once the queued inputs are used up.
+    pub fn with_input<I: Input<W> + Send + 'static>(mut self, input: I) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    // Send outputs to the sink, instead of collecting them in `outputs`.
+    pub fn with_output<O: Output<W> + Send + 'static>(mut self, output: O) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    pub(crate) fn read_input(&mut self) -> Option<W> {
+        match self.inputs.pop_front() {
+            Some(value) => Some(value),
+            None => self.input.as_mut().and_then(|input| input.read()),
+        }
+    }
+
+    pub(crate) fn write_output(&mut self, value: W) {
+        match &mut self.output {
+            Some(output) => output.write(value),
+            None => self.outputs.push_back(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{assemble, ExecutionOption, State, VmError};
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+
+    ide!();
+
+    // echoes its inputs, until it reads a zero
+    fn echo() -> Vec<i64> {
+        assemble(
+            "
+            loop:   in [x]
+                    jf [x], #end
+                    out [x]
+                    jt #1, #loop
+            end:    hlt
+            x:      data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn iterator() {
+        let mut vm = VM::new(echo()).with_input(IterInput::new(vec![1, 2, 3, 0]));
+        vm.execute(ExecutionOption::default()).unwrap();
+
+        assert_eq!(vm.outputs, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn queued_inputs_first() {
+        let mut vm = VM::with_inputs(echo(), vec![1, 2]).with_input(IterInput::new(3..));
+        vm.push_input(4);
+
+        assert_eq!(vm.run(), Ok(State::Output(1)));
+        assert_eq!(vm.run(), Ok(State::Output(2)));
+        assert_eq!(vm.run(), Ok(State::Output(4)));
+        assert_eq!(vm.run(), Ok(State::Output(3)));
+        assert_eq!(vm.run(), Ok(State::Output(4)));
+    }
+
+    #[test]
+    fn closures() {
+        let mut n = 3;
+        let outputs = Arc::new(Mutex::new(Vec::new()));
+        let sink = outputs.clone();
+
+        let mut vm = VM::new(echo())
+            .with_input(move || {
+                n -= 1;
+                Some(n * 10)
+            })
+            .with_output(move |value| sink.lock().unwrap().push(value));
+        vm.execute(ExecutionOption::default()).unwrap();
+
+        assert_eq!(*outputs.lock().unwrap(), vec![20, 10]);
+        assert!(vm.outputs.is_empty());
+    }
+
+    #[test]
+    fn channels() {
+        let (input, inputs) = channel();
+        let (outputs, output) = channel();
+        let mut vm = VM::new(echo()).with_input(inputs).with_output(outputs);
+
+        let handle = std::thread::spawn(move || vm.execute(ExecutionOption::default()));
+
+        input.send(5).unwrap();
+        assert_eq!(output.recv(), Ok(5));
+        input.send(6).unwrap();
+        assert_eq!(output.recv(), Ok(6));
+
+        // hanging up ends the inputs
+        drop(input);
+        assert!(matches!(
+            handle.join().unwrap(),
+            Err(VmError::InputExhausted { pc: 0 })
+        ));
+    }
+
+    #[test]
+    fn text_numeric() {
+        let text = "7\n-3\n\n8\n";
+        let mut vm = VM::new(echo()).with_input(TextInput::new(text.as_bytes(), TextMode::Numeric));
+
+        assert_eq!(vm.run(), Ok(State::Output(7)));
+        assert_eq!(vm.run(), Ok(State::Output(-3)));
+        // the empty line isn't a number, and the inputs stay ended
+        assert_eq!(vm.run(), Ok(State::NeedsInput));
+        assert_eq!(vm.run(), Ok(State::NeedsInput));
+    }
+
+    #[test]
+    fn text_ascii() {
+        let text = "hi\n";
+        let mut vm = VM::new(echo())
+            .with_input(TextInput::new(text.as_bytes(), TextMode::Ascii))
+            .with_output(TextOutput::new(Vec::new(), TextMode::Ascii));
+
+        assert_eq!(vm.run(), Ok(State::Output(104)));
+        assert_eq!(vm.run(), Ok(State::Output(105)));
+        assert_eq!(vm.run(), Ok(State::Output(10)));
+        assert_eq!(vm.run(), Ok(State::NeedsInput));
+    }
+
+    #[test]
+    fn text_output() {
+        let mut numeric = TextOutput::new(Vec::new(), TextMode::Numeric);
+        let mut ascii = TextOutput::new(Vec::new(), TextMode::Ascii);
+
+        for value in [72, 105, 10, 1234, -1].iter() {
+            numeric.write(*value);
+            ascii.write(*value);
+        }
+
+        assert_eq!(numeric.into_inner(), b"72\n105\n10\n1234\n-1\n");
+        assert_eq!(ascii.into_inner(), b"Hi\n1234\n-1\n");
+    }
+}
@@ -0,0 +1,361 @@
+use crate::vm::cache::{InstructionCache, MAX_CACHED_ADDRESS};
+use crate::vm::{Address, Instruction, VmError, Word};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+// Number of words in a page of sparse memory.
+const PAGE_SIZE: usize = 1024;
+
+// Dense memory is one allocation, so we don't let it grow unbounded.
+pub(crate) const DENSE_LIMIT: usize = 1 << 24;
+const SPARSE_LIMIT: usize = usize::MAX;
+
+// The memory of the VM. It owns its storage and is initialized with the program.
+//
+// Memory beyond the program is available too: addresses which weren't written to read as zero,
+// and writes beyond the end of the memory grow it.
+//
+// Memory is stored in pages which are shared between clones, so cloning is cheap: a page is only
+// copied when one of the clones writes to it.
+#[derive(Debug, Clone)]
+pub struct Memory<W: Word> {
+    storage: Storage<W>,
+
+    // one past the highest address which was loaded or written to
+    len: usize,
+
+    // accessing an address at, or beyond, the limit is out of bounds
+    limit: usize,
+
+    // the instructions decoded from this memory, unless disabled
+    decoded: Option<InstructionCache<W>>,
+}
+
+// An access out of the bounds of the memory, at the contained address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OutOfBounds(pub Address);
+
+type Page<W> = Arc<Vec<W>>;
+
+#[derive(Debug, Clone)]
+enum Storage<W: Word> {
+    // consecutive pages of PAGE_SIZE words, starting at address 0
+    Dense(Vec<Page<W>>),
+    // pages of PAGE_SIZE words, only allocated when written to; for programs which poke
+    // at very high addresses
+    Sparse(HashMap<usize, Page<W>>),
+}
+
+impl<W: Word> Memory<W> {
+    pub fn new(program: Vec<W>) -> Self {
+        let pages = program
+            .chunks(PAGE_SIZE)
+            .map(|chunk| {
+                let mut page = chunk.to_vec();
+                page.resize(PAGE_SIZE, W::zero());
+                Arc::new(page)
+            })
+            .collect();
+
+        Self {
+            len: program.len(),
+            limit: DENSE_LIMIT.max(program.len()),
+            storage: Storage::Dense(pages),
+            decoded: Some(InstructionCache::new()),
+        }
+    }
+
+    pub fn sparse(program: Vec<W>) -> Self {
+        let mut memory = Self {
+            len: 0,
+            limit: SPARSE_LIMIT,
+            storage: Storage::Sparse(HashMap::new()),
+            decoded: Some(InstructionCache::new()),
+        };
+
+        for (addr, word) in program.into_iter().enumerate() {
+            memory.write_unchecked(addr, word);
+        }
+
+        memory
+    }
+
+    // Addresses at or beyond the limit will be out of bounds.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn read(&self, addr: Address) -> Result<W, OutOfBounds> {
+        if addr >= self.limit {
+            return Err(OutOfBounds(addr));
+        }
+
+        let page = match &self.storage {
+            Storage::Dense(pages) => pages.get(addr / PAGE_SIZE),
+            Storage::Sparse(pages) => pages.get(&(addr / PAGE_SIZE)),
+        };
+        let word = page.map(|page| &page[addr % PAGE_SIZE]);
+
+        Ok(word.cloned().unwrap_or_else(W::zero))
+    }
+
+    pub fn write(&mut self, addr: Address, value: W) -> Result<(), OutOfBounds> {
+        if addr >= self.limit {
+            return Err(OutOfBounds(addr));
+        }
+
+        self.write_unchecked(addr, value);
+        Ok(())
+    }
+
+    fn write_unchecked(&mut self, addr: Address, value: W) {
+        let n = addr / PAGE_SIZE;
+        let zeroes = || Arc::new(vec![W::zero(); PAGE_SIZE]);
+
+        let page = match &mut self.storage {
+            Storage::Dense(pages) => {
+                if n >= pages.len() {
+                    pages.resize_with(n + 1, zeroes);
+                }
+
+                &mut pages[n]
+            }
+            Storage::Sparse(pages) => pages.entry(n).or_insert_with(zeroes),
+        };
+
+        // copies the page if it's shared with a clone
+        Arc::make_mut(page)[addr % PAGE_SIZE] = value;
+
+        self.len = self.len.max(addr + 1);
+
+        if let Some(decoded) = &mut self.decoded {
+            decoded.invalidate(addr);
+        }
+    }
+
+    // The instruction at the address; decoded only the first time, if the cache is enabled.
+    pub(crate) fn fetch(&mut self, pc: Address) -> Result<Instruction<W>, VmError<W>> {
+        if let Some(instruction) = self.decoded.as_ref().and_then(|cache| cache.get(pc)) {
+            return Ok(instruction.clone());
+        }
+
+        let instruction = Instruction::fetch(self, pc)?;
+
+        if let Some(decoded) = &mut self.decoded {
+            decoded.insert(pc, instruction.clone());
+        }
+
+        Ok(instruction)
+    }
+
+    // Fill the cache up front, by decoding the memory from address 0 with a linear sweep.
+    // Words which don't decode are skipped, and so are addresses which aren't cached anyway.
+    pub(crate) fn predecode(&mut self) {
+        let mut pc = 0;
+
+        while self.decoded.is_some() && pc < self.len.min(MAX_CACHED_ADDRESS) {
+            pc += self.fetch(pc).map_or(1, |instruction| instruction.len());
+        }
+    }
+
+    pub(crate) fn disable_instruction_cache(&mut self) {
+        self.decoded = None;
+    }
+
+    // One past the highest address which was loaded or written to.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.storage, Storage::Sparse(_))
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    // The words up to the length of the memory, as runs of consecutive words by their start
+    // address. Pages of sparse memory which were never written to are left out.
+    pub(crate) fn segments(&self) -> Vec<(Address, Vec<W>)> {
+        let mut segments: Vec<(Address, Vec<W>)> = Vec::new();
+
+        for (n, page) in self.pages() {
+            let start = n * PAGE_SIZE;
+            if start >= self.len {
+                continue;
+            }
+
+            let words = &page[..PAGE_SIZE.min(self.len - start)];
+
+            match segments.last_mut() {
+                Some((addr, run)) if *addr + run.len() == start => run.extend_from_slice(words),
+                _ => segments.push((start, words.to_vec())),
+            }
+        }
+
+        segments
+    }
+
+    // Feed the words of the memory to the hasher. Memories with equal words, which allocated the
+    // same pages, hash the same.
+    pub(crate) fn hash_words<H: Hasher>(&self, state: &mut H) {
+        for (n, page) in self.pages() {
+            n.hash(state);
+            page.hash(state);
+        }
+    }
+
+    // The allocated pages, by their number.
+    fn pages(&self) -> Vec<(usize, &Page<W>)> {
+        let mut pages: Vec<(usize, &Page<W>)> = match &self.storage {
+            Storage::Dense(pages) => pages.iter().enumerate().collect(),
+            Storage::Sparse(pages) => pages.iter().map(|(&n, page)| (n, page)).collect(),
+        };
+        pages.sort_by_key(|&(n, _)| n);
+
+        pages
+    }
+
+    // The inverse of `segments`.
+    pub(crate) fn from_segments(
+        sparse: bool,
+        limit: usize,
+        segments: Vec<(Address, Vec<W>)>,
+    ) -> Result<Self, OutOfBounds> {
+        let memory = if sparse {
+            Memory::sparse(Vec::new())
+        } else {
+            Memory::new(Vec::new())
+        };
+        let mut memory = memory.with_limit(limit);
+
+        for (start, words) in segments {
+            for (i, word) in words.into_iter().enumerate() {
+                let addr = start.checked_add(i).ok_or(OutOfBounds(start))?;
+                memory.write(addr, word)?;
+            }
+        }
+
+        Ok(memory)
+    }
+}
+
+impl<W: Word> From<Vec<W>> for Memory<W> {
+    fn from(program: Vec<W>) -> Self {
+        Memory::new(program)
+    }
+}
+
+impl<W: Word> From<&[W]> for Memory<W> {
+    fn from(program: &[W]) -> Self {
+        Memory::new(program.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parameterized::parameterized as pm;
+
+    ide!();
+
+    #[pm(memory = {
+        Memory::new(vec![1, 2, 3]),
+        Memory::sparse(vec![1, 2, 3]),
+    })]
+    fn untouched_reads_zero(memory: Memory<i64>) {
+        assert_eq!(memory.read(2), Ok(3));
+        assert_eq!(memory.read(3), Ok(0));
+        assert_eq!(memory.read(1_000_000), Ok(0));
+        assert_eq!(memory.len(), 3);
+    }
+
+    #[pm(memory = {
+        Memory::new(vec![1, 2, 3]),
+        Memory::sparse(vec![1, 2, 3]),
+    })]
+    fn writes_grow(memory: Memory<i64>) {
+        let mut memory = memory;
+        memory.write(10, 42).unwrap();
+
+        assert_eq!(memory.read(10), Ok(42));
+        assert_eq!(memory.read(9), Ok(0));
+        assert_eq!(memory.read(0), Ok(1));
+        assert_eq!(memory.len(), 11);
+    }
+
+    #[pm(memory = {
+        Memory::new((0..3000).collect()),
+        Memory::sparse((0..3000).collect()),
+    })]
+    fn clones_share_pages(memory: Memory<i64>) {
+        let mut clone = memory.clone();
+        clone.write(1500, -1).unwrap();
+        clone.write(5000, -2).unwrap();
+
+        assert_eq!(memory.read(1500), Ok(1500));
+        assert_eq!(memory.read(5000), Ok(0));
+        assert_eq!(memory.len(), 3000);
+        assert_eq!(clone.read(1500), Ok(-1));
+        assert_eq!(clone.read(5000), Ok(-2));
+        assert_eq!(clone.read(1499), Ok(1499));
+        assert_eq!(clone.len(), 5001);
+    }
+
+    #[pm(memory = {
+        Memory::new((1..=3000).collect()),
+        Memory::sparse((1..=3000).collect()),
+        Memory::sparse(vec![]),
+    })]
+    fn segments(memory: Memory<i64>) {
+        let mut memory = memory;
+        memory.write(9000, 7).unwrap();
+
+        let segments = memory.segments();
+        let restored =
+            Memory::from_segments(memory.is_sparse(), memory.limit(), segments.clone()).unwrap();
+
+        assert_eq!(restored.len(), memory.len());
+        assert_eq!(restored.segments(), segments);
+        assert_eq!(restored.read(9000), Ok(7));
+
+        if memory.is_sparse() {
+            // the untouched pages in between are left out
+            assert_eq!(
+                segments.last().map(|(start, _)| *start),
+                Some(9000 / PAGE_SIZE * PAGE_SIZE)
+            );
+        } else {
+            assert_eq!(segments.len(), 1);
+        }
+    }
+
+    #[test]
+    fn sparse_high_address() {
+        let mut memory = Memory::sparse(vec![1, 2, 3]);
+        memory.write(1 << 40, 7).unwrap();
+
+        assert_eq!(memory.read(1 << 40), Ok(7));
+        assert_eq!(memory.read((1 << 40) - 1), Ok(0));
+        assert_eq!(memory.len(), (1 << 40) + 1);
+    }
+
+    #[test]
+    fn limit() {
+        let mut memory = Memory::new(vec![1, 2, 3]).with_limit(8);
+
+        assert_eq!(memory.write(7, 4), Ok(()));
+        assert_eq!(memory.write(8, 4), Err(OutOfBounds(8)));
+        assert_eq!(memory.read(8), Err(OutOfBounds(8)));
+
+        let memory = Memory::<i64>::new(vec![]);
+        assert_eq!(memory.read(1 << 40), Err(OutOfBounds(1 << 40)));
+    }
+}
@@ -1,20 +1,59 @@
 // TODO:
-// * Error handling
 // * make an iterator around eval()
 // * Transorm Param to InParam and OutParam, which may improve readability
 // * Opcodes back to u8 (?) (or too much casting)
 
-use anyhow::{bail, Context, Result};
+use memory::OutOfBounds;
 use opcode::*;
-use std::collections::VecDeque;
-use std::ops::{Add, Mul};
+use std::cell::Cell;
+use std::collections::{HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+
+pub use amplifier::{AmplifierChain, ChainOutput, Topology};
+pub use asm::{assemble, AsmError};
+pub use console::{decode, encode, Console, Decoded, Reply};
+pub use debugger::Debugger;
+pub use disasm::disassemble;
+pub use error::VmError;
+pub use flow::{analyze, Block, ControlFlowGraph, Flow};
+pub use io::{Input, IterInput, Output, TextInput, TextMode, TextOutput};
+pub use memory::Memory;
+pub use network::{Event, Network, NetworkError, NetworkOutput, Reaction};
+pub use outcome::{Halt, Outcome, RunResult};
+pub use patch::{CodePatch, PatchDetector, PatchStats};
+pub use profile::{Loop, Profile};
+pub use search::Search;
+pub use snapshot::Snapshot;
+pub use state::{Format, StateError};
+pub use symbolic::{Polynomial, Symbolic, SymbolicError, Value};
+pub use trace::{Observer, Recorder, TraceEvent, TraceWriter};
+pub use word::Word;
+
+mod amplifier;
+mod asm;
+mod cache;
+mod console;
+mod debugger;
+mod disasm;
+mod error;
+mod flow;
+mod io;
+mod memory;
+mod network;
+mod outcome;
+mod patch;
+mod profile;
+mod runaway;
+mod search;
+mod snapshot;
+mod state;
+mod symbolic;
+mod trace;
+mod word;
 
 // We use usize as address since slices indexes use usize
 pub type Address = usize;
 
-// The minimal accessible unit. From day 5 it should support negative numbers which
-// introduces some complications, by needing to translate between a word value and an address.
-pub type Word = i32;
 // was u8, but i32 for now means less casting
 pub type Opcode = i32;
 
@@ -29,11 +68,13 @@ mod opcode {
     pub const OPCODE_JUMP_IF_FALSE: Opcode = 6;
     pub const OPCODE_LT: Opcode = 7;
     pub const OPCODE_EQ: Opcode = 8;
+    pub const OPCODE_ADJUST_RELATIVE_BASE: Opcode = 9;
     pub const OPCODE_RET: Opcode = 99;
 }
 
 const PARAM1: i32 = 1;
 const PARAM2: i32 = 10;
+const PARAM3: i32 = 100;
 
 // The instruction decoder.
 //
@@ -43,213 +84,360 @@ const PARAM2: i32 = 10;
 // C - 1st param mode
 // B - 2nd param mode
 // A - 3rd param mode (currently always an output)
-#[derive(Debug, Copy, Clone)]
-pub enum InParam {
-    Position(Address), // mode 0
-    Immediate(Word),   // mode 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InParam<W: Word> {
+    Position(W),  // mode 0
+    Immediate(W), // mode 1
+    Relative(W),  // mode 2
 }
 
-impl InParam {
-    fn new(opcode: Opcode, param_scale: Word, value: Word) -> Self {
+impl<W: Word> InParam<W> {
+    fn new(
+        opcode: Opcode,
+        param_scale: Opcode,
+        value: &W,
+        pc: Address,
+    ) -> Result<Self, VmError<W>> {
         match opcode / (100 * param_scale) % 10 {
-            0 => InParam::Position(value as usize),
-            1 => InParam::Immediate(value),
-            _ => panic!("param1 . TODO error handling..."),
+            0 => Ok(InParam::Position(value.clone())),
+            1 => Ok(InParam::Immediate(value.clone())),
+            2 => Ok(InParam::Relative(value.clone())),
+            mode => Err(VmError::BadParameterMode { pc, opcode, mode }),
         }
     }
 
-    // read from the tape, method depends on the mode.
-    fn read(&self, tape: &[Word]) -> Word {
+    // read from the tape, method depends on the mode. Addresses are only checked when they're
+    // used, e.g. the target of a jump which isn't taken may be negative.
+    fn read(&self, vm: &VM<W>) -> Result<W, VmError<W>> {
         match self {
-            InParam::Position(addr) => tape[*addr],
-            InParam::Immediate(w) => *w,
+            InParam::Position(addr) => vm.load(to_address(addr, vm.pc)?),
+            InParam::Immediate(w) => Ok(w.clone()),
+            InParam::Relative(offset) => vm.load(relative_address(vm, offset)?),
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct OutParam {
-    addr: Address,
+// Rendered as `[12]` (position), `#5` (immediate) or `rb+3` (relative)
+impl<W: Word> Display for InParam<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InParam::Position(addr) => write!(f, "[{}]", addr),
+            InParam::Immediate(w) => write!(f, "#{}", w),
+            InParam::Relative(offset) => fmt_relative(f, offset),
+        }
+    }
 }
 
-impl OutParam {
-    fn new(addr: Address) -> Self {
-        OutParam { addr }
+// Parameters which are written to can't be in immediate mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutParam<W: Word> {
+    Position(W), // mode 0
+    Relative(W), // mode 2
+}
+
+impl<W: Word> OutParam<W> {
+    fn new(
+        opcode: Opcode,
+        param_scale: Opcode,
+        value: &W,
+        pc: Address,
+    ) -> Result<Self, VmError<W>> {
+        match opcode / (100 * param_scale) % 10 {
+            0 => Ok(OutParam::Position(value.clone())),
+            1 => Err(VmError::WriteInImmediateMode { pc }),
+            2 => Ok(OutParam::Relative(value.clone())),
+            mode => Err(VmError::BadParameterMode { pc, opcode, mode }),
+        }
     }
 
     /// Write the tape
-    fn write(self, tape: &mut [Word], value: Word) {
-        tape[self.addr] = value;
+    fn write(&self, vm: &mut VM<W>, value: W) -> Result<(), VmError<W>> {
+        let addr = match self {
+            OutParam::Position(addr) => to_address(addr, vm.pc)?,
+            OutParam::Relative(offset) => relative_address(vm, offset)?,
+        };
+
+        vm.store(addr, value)
     }
 }
 
-#[derive(Debug)]
-pub enum Instruction {
+impl<W: Word> Display for OutParam<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutParam::Position(addr) => write!(f, "[{}]", addr),
+            OutParam::Relative(offset) => fmt_relative(f, offset),
+        }
+    }
+}
+
+fn fmt_relative<W: Word>(f: &mut Formatter<'_>, offset: &W) -> std::fmt::Result {
+    if *offset < W::zero() {
+        write!(f, "rb{}", offset)
+    } else {
+        write!(f, "rb+{}", offset)
+    }
+}
+
+// A word used as address should be positive, and should fit in an `Address`.
+pub(crate) fn to_address<W: Word>(value: &W, pc: Address) -> Result<Address, VmError<W>> {
+    value.to_usize().ok_or_else(|| VmError::NegativeAddress {
+        pc,
+        value: value.clone(),
+    })
+}
+
+fn relative_address<W: Word>(vm: &VM<W>, offset: &W) -> Result<Address, VmError<W>> {
+    let addr = vm
+        .relative_base
+        .checked_add(offset)
+        .ok_or(VmError::Overflow { pc: vm.pc })?;
+
+    to_address(&addr, vm.pc)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction<W: Word> {
     // (operation, first param, second param, third param)
-    Binop(BinopInstr, [InParam; 2], OutParam),
-    Input(OutParam),
-    Output(InParam),
-    JumpIfTrue([InParam; 2]),
-    JumpIfFalse([InParam; 2]),
-    LessThan([InParam; 2], OutParam), // fixme: generalize
-    Equals([InParam; 2], OutParam),
+    Binop(BinopInstr, [InParam<W>; 2], OutParam<W>),
+    Input(OutParam<W>),
+    Output(InParam<W>),
+    JumpIfTrue([InParam<W>; 2]),
+    JumpIfFalse([InParam<W>; 2]),
+    LessThan([InParam<W>; 2], OutParam<W>), // fixme: generalize
+    Equals([InParam<W>; 2], OutParam<W>),
+    AdjustRelativeBase(InParam<W>),
     Ret,
 }
 
-impl Instruction {
-    fn fetch(tape: &[Word], pc: Address) -> Result<Self> {
-        let opcode = tape[pc];
+impl<W: Word> Instruction<W> {
+    pub fn fetch(tape: &Memory<W>, pc: Address) -> Result<Self, VmError<W>> {
+        let read = |addr: Address| {
+            tape.read(addr)
+                .map_err(|OutOfBounds(address)| VmError::OutOfBounds { pc, address })
+        };
+
+        let word = read(pc)?;
+        let opcode = word.to_i32().ok_or_else(|| VmError::UnknownOpcode {
+            pc,
+            opcode: word.clone(),
+        })?;
+
+        let in_param = |n: usize, param_scale: Opcode| {
+            InParam::new(opcode, param_scale, &read(pc.saturating_add(n))?, pc)
+        };
+        let out_param = |n: usize, param_scale: Opcode| {
+            OutParam::new(opcode, param_scale, &read(pc.saturating_add(n))?, pc)
+        };
 
         match opcode % 100 {
-            OPCODE_ADD => Ok(Instruction::mk_binop(BinopInstr::Add, opcode, tape, pc)),
-            OPCODE_MUL => Ok(Instruction::mk_binop(BinopInstr::Mul, opcode, tape, pc)),
-            OPCODE_INPUT => Ok(Instruction::Input(OutParam::new(tape[pc + 1] as usize))),
-            OPCODE_OUTPUT => Ok(Instruction::Output(InParam::new(
-                opcode,
-                PARAM1,
-                tape[pc + 1],
-            ))),
+            OPCODE_ADD => Ok(Instruction::Binop(
+                BinopInstr::Add,
+                [in_param(1, PARAM1)?, in_param(2, PARAM2)?],
+                out_param(3, PARAM3)?,
+            )),
+            OPCODE_MUL => Ok(Instruction::Binop(
+                BinopInstr::Mul,
+                [in_param(1, PARAM1)?, in_param(2, PARAM2)?],
+                out_param(3, PARAM3)?,
+            )),
+            OPCODE_INPUT => Ok(Instruction::Input(out_param(1, PARAM1)?)),
+            OPCODE_OUTPUT => Ok(Instruction::Output(in_param(1, PARAM1)?)),
             OPCODE_JUMP_IF_TRUE => Ok(Instruction::JumpIfTrue([
-                InParam::new(opcode, PARAM1, tape[pc + 1]),
-                InParam::new(opcode, PARAM2, tape[pc + 2]),
+                in_param(1, PARAM1)?,
+                in_param(2, PARAM2)?,
             ])),
             OPCODE_JUMP_IF_FALSE => Ok(Instruction::JumpIfFalse([
-                InParam::new(opcode, PARAM1, tape[pc + 1]),
-                InParam::new(opcode, PARAM2, tape[pc + 2]),
+                in_param(1, PARAM1)?,
+                in_param(2, PARAM2)?,
             ])),
             OPCODE_LT => Ok(Instruction::LessThan(
-                [
-                    InParam::new(opcode, PARAM1, tape[pc + 1]),
-                    InParam::new(opcode, PARAM2, tape[pc + 2]),
-                ],
-                OutParam::new(tape[pc + 3] as usize),
+                [in_param(1, PARAM1)?, in_param(2, PARAM2)?],
+                out_param(3, PARAM3)?,
             )),
             OPCODE_EQ => Ok(Instruction::Equals(
-                [
-                    InParam::new(opcode, PARAM1, tape[pc + 1]),
-                    InParam::new(opcode, PARAM2, tape[pc + 2]),
-                ],
-                OutParam::new(tape[pc + 3] as usize),
+                [in_param(1, PARAM1)?, in_param(2, PARAM2)?],
+                out_param(3, PARAM3)?,
             )),
+            OPCODE_ADJUST_RELATIVE_BASE => {
+                Ok(Instruction::AdjustRelativeBase(in_param(1, PARAM1)?))
+            }
             OPCODE_RET => Ok(Instruction::Ret),
-            _ => bail!("Opcode could not be fetched. Opcode may be invalid. "),
+            _ => Err(VmError::UnknownOpcode { pc, opcode: word }),
         }
     }
 
-    fn eval(&self, vm: &mut VM) -> Option<()> {
-        match self {
-            Instruction::Binop(op, params, out) => {
-                match op {
-                    BinopInstr::Add => binop(Word::add)(vm.tape, *params, *out),
-                    BinopInstr::Mul => binop(Word::mul)(vm.tape, *params, *out),
-                }
-
-                Some(())
-            }
-            Instruction::Input(out) => {
-                let value = vm.inputs.next().expect("eval . TODO");
-                out.write(vm.tape, value);
+    fn eval(&self, vm: &mut VM<W>) -> Result<State<W>, VmError<W>> {
+        let mut state = State::Running;
 
-                Some(())
-            }
+        match self {
+            Instruction::Binop(op, params, out) => match op {
+                BinopInstr::Add => binop(W::checked_add)(vm, params, out)?,
+                BinopInstr::Mul => binop(W::checked_mul)(vm, params, out)?,
+            },
+            Instruction::Input(out) => match vm.read_input() {
+                Some(value) => out.write(vm, value)?,
+                // keep the pc at this instruction, so we can resume once input is provided
+                None => return Ok(State::NeedsInput),
+            },
             Instruction::Output(param) => {
-                let value = param.read(vm.tape);
-                vm.outputs.push_front(value);
-
-                Some(())
+                let value = param.read(vm)?;
+                vm.write_output(value.clone());
+                state = State::Output(value);
             }
             Instruction::JumpIfTrue(params) => {
-                //jump_if(Word::eq)(vm, *params), // fixme see below
-
-                let cond = params[0].read(vm.tape);
-
-                if cond != 0 {
-                    let jump_addr = params[1].read(vm.tape);
-                    vm.pc = jump_addr as usize;
-                } else {
-                    vm.pc += 3; // fixme: see len()
-                }
-
-                Some(())
+                // jumps set the pc themselves
+                jump_if(|cond: &W| !cond.is_zero())(vm, params, self.len())?;
+                return Ok(State::Running);
             }
             Instruction::JumpIfFalse(params) => {
-                // jump_if(Word::ne)(vm, *params) // fixme see below
-                let cond = params[0].read(vm.tape);
-
-                if cond == 0 {
-                    let jump_addr = params[1].read(vm.tape);
-                    vm.pc = jump_addr as usize;
-                } else {
-                    vm.pc += 3; // fixme: see len()
-                }
-
-                Some(())
+                jump_if(W::is_zero)(vm, params, self.len())?;
+                return Ok(State::Running);
             }
             Instruction::LessThan(params, out) => {
-                // jump_if(Word::ne)(vm, *params) // fixme see below
-                let this = params[0].read(vm.tape);
-                let other = params[1].read(vm.tape);
+                let this = params[0].read(vm)?;
+                let other = params[1].read(vm)?;
 
                 if this < other {
-                    out.write(vm.tape, 1);
+                    out.write(vm, W::one())?;
                 } else {
-                    out.write(vm.tape, 0);
+                    out.write(vm, W::zero())?;
                 };
-
-                Some(())
             }
             Instruction::Equals(params, out) => {
-                // jump_if(Word::ne)(vm, *params) // fixme see below
-                let this = params[0].read(vm.tape);
-                let other = params[1].read(vm.tape);
+                let this = params[0].read(vm)?;
+                let other = params[1].read(vm)?;
 
                 if this == other {
-                    out.write(vm.tape, 1);
+                    out.write(vm, W::one())?;
                 } else {
-                    out.write(vm.tape, 0);
+                    out.write(vm, W::zero())?;
                 };
-
-                Some(())
             }
-            Instruction::Ret => None,
+            Instruction::AdjustRelativeBase(param) => {
+                let adjustment = param.read(vm)?;
+                vm.relative_base = vm
+                    .relative_base
+                    .checked_add(&adjustment)
+                    .ok_or(VmError::Overflow { pc: vm.pc })?;
+            }
+            // the pc stays at the halting instruction
+            Instruction::Ret => return Ok(State::Halted),
         }
+
+        vm.pc += self.len();
+
+        Ok(state)
     }
 
-    fn len(&self) -> usize {
+    // The number of words the instruction occupies, including its parameters.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
         match self {
             Instruction::Binop(_, _, _) => 4,
             Instruction::Input(_) => 2,
             Instruction::Output(_) => 2,
-            Instruction::JumpIfTrue(_) => 0, //3, TODO can be different if jumping
-            Instruction::JumpIfFalse(_) => 0, //3
+            Instruction::JumpIfTrue(_) => 3,
+            Instruction::JumpIfFalse(_) => 3,
             Instruction::LessThan(_, _) => 4,
             Instruction::Equals(_, _) => 4,
+            Instruction::AdjustRelativeBase(_) => 2,
             Instruction::Ret => 1,
         }
     }
 
-    fn mk_binop(which: BinopInstr, opcode: Opcode, tape: &[Word], pc: Address) -> Self {
-        Instruction::Binop(
-            which,
-            [
-                InParam::new(opcode, PARAM1, tape[pc + 1]),
-                InParam::new(opcode, PARAM2, tape[pc + 2]),
-            ],
-            OutParam::new(tape[pc + 3] as usize),
+    // The parameters which are read by the instruction.
+    pub fn params(&self) -> &[InParam<W>] {
+        match self {
+            Instruction::Binop(_, params, _)
+            | Instruction::JumpIfTrue(params)
+            | Instruction::JumpIfFalse(params)
+            | Instruction::LessThan(params, _)
+            | Instruction::Equals(params, _) => params,
+            Instruction::Output(param) | Instruction::AdjustRelativeBase(param) => {
+                std::slice::from_ref(param)
+            }
+            Instruction::Input(_) | Instruction::Ret => &[],
+        }
+    }
+
+    pub fn is_jump(&self) -> bool {
+        matches!(
+            self,
+            Instruction::JumpIfTrue(_) | Instruction::JumpIfFalse(_)
         )
     }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Binop(BinopInstr::Add, _, _) => "add",
+            Instruction::Binop(BinopInstr::Mul, _, _) => "mul",
+            Instruction::Input(_) => "in",
+            Instruction::Output(_) => "out",
+            Instruction::JumpIfTrue(_) => "jt",
+            Instruction::JumpIfFalse(_) => "jf",
+            Instruction::LessThan(_, _) => "lt",
+            Instruction::Equals(_, _) => "eq",
+            Instruction::AdjustRelativeBase(_) => "arb",
+            Instruction::Ret => "hlt",
+        }
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
+// Rendered as assembly, e.g. `add [9], #3, rb-1`
+impl<W: Word> Display for Instruction<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mnemonic = self.mnemonic();
+
+        match self {
+            Instruction::Binop(_, [x, y], out)
+            | Instruction::LessThan([x, y], out)
+            | Instruction::Equals([x, y], out) => {
+                write!(f, "{} {}, {}, {}", mnemonic, x, y, out)
+            }
+            Instruction::Input(out) => write!(f, "{} {}", mnemonic, out),
+            Instruction::Output(param) | Instruction::AdjustRelativeBase(param) => {
+                write!(f, "{} {}", mnemonic, param)
+            }
+            Instruction::JumpIfTrue([cond, addr]) | Instruction::JumpIfFalse([cond, addr]) => {
+                write!(f, "{} {}, {}", mnemonic, cond, addr)
+            }
+            Instruction::Ret => write!(f, "{}", mnemonic),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum BinopInstr {
     Add,
     Mul,
 }
 
+// The state of the VM after executing one or more instructions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum State<W: Word> {
+    // may continue with the next instruction
+    Running,
+    // paused at an input instruction, until an input is pushed
+    NeedsInput,
+    // an output instruction was executed, which produced the value
+    Output(W),
+    // reached the halting instruction; continuing will stay halted
+    Halted,
+}
+
+// What `execute` returns, once the program halted.
 #[derive(Debug, Copy, Clone)]
 pub enum ExecutionOption {
+    // the word at the address, as `Outcome::Word`
     OutputByAddress(usize),
+    // the last output, as `Outcome::Word`
     OutputByTapeOutput,
+    // all outputs in emission order, as `Outcome::Outputs`
+    AllOutputs,
+    // the last n outputs (or fewer) in emission order, as `Outcome::Outputs`
+    LastOutputs(usize),
+    // everything, as `Outcome::Run`; also when the program fails
+    RunResult,
 }
 
 impl Default for ExecutionOption {
@@ -258,85 +446,494 @@ impl Default for ExecutionOption {
     }
 }
 
-pub struct VM<'a> {
+pub struct VM<W: Word = i64> {
     // our memory
-    pub tape: &'a mut [Word],
+    pub tape: Memory<W>,
 
     // program counter (= instruction pointer)
     pub pc: Address,
 
-    // inputs if any
-    pub inputs: Box<dyn Iterator<Item = Word>>,
+    // base address for parameters in relative mode
+    pub relative_base: W,
+
+    // inputs if any, consumed from the front
+    pub inputs: VecDeque<W>,
+
+    // outputs if any, in emission order
+    pub outputs: VecDeque<W>,
+
+    // read once the inputs are used up, if any
+    input: Option<Box<dyn Input<W> + Send>>,
+
+    // receives the outputs instead, if any
+    output: Option<Box<dyn Output<W> + Send>>,
+
+    // watches the execution, if any
+    observer: Option<Box<dyn Observer<W> + Send>>,
+
+    // execution statistics, if enabled
+    profile: Option<Profile>,
 
-    // outputs, if any
-    pub outputs: VecDeque<Word>,
+    // the highest address read or written by the current instruction, while profiling
+    touched: Cell<Address>,
+
+    // the number of executed instructions
+    steps: u64,
+
+    // guards against programs which don't halt, if enabled
+    step_limit: Option<u64>,
+    visited: Option<HashSet<u64>>,
 }
 
-impl<'a> VM<'a> {
-    pub fn new(tape: &'a mut [Word]) -> Self {
+impl<W: Word> VM<W> {
+    // A VM running the program. The program is decoded up front, so forks of this VM share the
+    // decoded instructions.
+    pub fn new<M: Into<Memory<W>>>(program: M) -> Self {
+        let mut vm = Self::with_memory(program.into());
+        vm.tape.predecode();
+        vm
+    }
+
+    fn with_memory(tape: Memory<W>) -> Self {
         Self {
             tape,
             pc: 0,
-            inputs: Box::new(VecDeque::new().into_iter()),
+            relative_base: W::zero(),
+            inputs: VecDeque::new(),
             outputs: VecDeque::new(),
+            input: None,
+            output: None,
+            observer: None,
+            profile: None,
+            touched: Cell::new(0),
+            steps: 0,
+            step_limit: None,
+            visited: None,
         }
     }
 
-    pub fn with_inputs<I>(program: &'a mut [Word], inputs: I) -> Self
+    pub fn with_inputs<M, I>(program: M, inputs: I) -> Self
     where
-        I: IntoIterator<Item = Word>,
-        <I as IntoIterator>::IntoIter: 'static,
+        M: Into<Memory<W>>,
+        I: IntoIterator<Item = W>,
     {
-        Self {
-            tape: program,
-            pc: 0,
-            inputs: Box::new(inputs.into_iter()),
-            outputs: VecDeque::new(),
-        }
+        let mut vm = Self::new(program);
+        vm.inputs = inputs.into_iter().collect();
+        vm
     }
 
-    pub fn execute(&mut self, output_type: ExecutionOption) -> anyhow::Result<Word> {
-        while let Ok(fetched) = Instruction::fetch(self.tape, self.pc) {
-            self.pc += fetched.len();
+    // Report each executed instruction and each memory write to the observer.
+    pub fn with_observer<O: Observer<W> + Send + 'static>(mut self, observer: O) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
 
-            if fetched.eval(self).is_none() {
-                break;
+    // Decode each instruction every time it's executed, instead of caching decoded instructions.
+    pub fn without_instruction_cache(mut self) -> Self {
+        self.tape.disable_instruction_cache();
+        self
+    }
+
+    pub fn take_observer(&mut self) -> Option<Box<dyn Observer<W> + Send>> {
+        self.observer.take()
+    }
+
+    // Provide an input, consumed after the inputs which were provided earlier.
+    pub fn push_input(&mut self, input: W) {
+        self.inputs.push_back(input);
+    }
+
+    // Execute a single instruction.
+    pub fn step(&mut self) -> Result<State<W>, VmError<W>> {
+        let pc = self.pc;
+        let fetched = self.tape.fetch(pc)?;
+        // a halted VM stays halted, however often it's resumed, so halting isn't a step
+        let halting = fetched == Instruction::Ret;
+
+        if let Some(limit) = self.step_limit.filter(|_| !halting) {
+            if self.steps >= limit {
+                return Err(VmError::StepLimitExceeded { pc, limit });
+            }
+        }
+
+        if self.observer.is_some() {
+            // best effort: the instruction itself decides which parameters it reads
+            let params = fetched
+                .params()
+                .iter()
+                .map(|param| param.read(self).ok())
+                .collect::<Vec<_>>();
+
+            if let Some(observer) = &mut self.observer {
+                observer.instruction(self.pc, &fetched, &params);
+            }
+        }
+
+        let state = if self.profile.is_some() {
+            self.eval_profiled(&fetched)?
+        } else {
+            fetched.eval(self)?
+        };
+
+        if state != State::NeedsInput && !halting {
+            self.steps += 1;
+
+            if self.visited.is_some() {
+                self.detect_loop(pc, &fetched)?;
+            }
+        }
+
+        Ok(state)
+    }
+
+    // Execute instructions until the VM needs an input, produces an output or halts.
+    // Afterwards, the VM can be resumed by calling `run` (or `step`) again.
+    pub fn run(&mut self) -> Result<State<W>, VmError<W>> {
+        loop {
+            match self.step()? {
+                State::Running => continue,
+                state => return Ok(state),
             }
         }
+    }
 
-        match output_type {
-            ExecutionOption::OutputByAddress(n) => Ok(self.tape[n]),
-            ExecutionOption::OutputByTapeOutput => Ok(*self
+    // Run the program to completion.
+    pub fn execute(&mut self, output_type: ExecutionOption) -> Result<Outcome<W>, VmError<W>> {
+        let halted = self.run_to_halt();
+        let outputs = || self.outputs.iter().cloned();
+
+        match (output_type, halted) {
+            (ExecutionOption::RunResult, halted) => Ok(Outcome::Run(RunResult {
+                memory: self.tape.clone(),
+                halt: match halted {
+                    Ok(()) => Halt::Halted { pc: self.pc },
+                    Err(error) => Halt::Failed(error),
+                },
+                outputs: outputs().collect(),
+            })),
+            (_, Err(error)) => Err(error),
+            (ExecutionOption::OutputByAddress(n), Ok(())) => self.load(n).map(Outcome::Word),
+            (ExecutionOption::OutputByTapeOutput, Ok(())) => self
                 .outputs
-                .front()
-                .with_context(|| "output . TODO more than one output")?),
+                .back()
+                .cloned()
+                .map(Outcome::Word)
+                .ok_or(VmError::NoOutput { pc: self.pc }),
+            (ExecutionOption::AllOutputs, Ok(())) => Ok(Outcome::Outputs(outputs().collect())),
+            (ExecutionOption::LastOutputs(n), Ok(())) => {
+                let skip = self.outputs.len().saturating_sub(n);
+                Ok(Outcome::Outputs(outputs().skip(skip).collect()))
+            }
+        }
+    }
+
+    fn run_to_halt(&mut self) -> Result<(), VmError<W>> {
+        loop {
+            match self.run()? {
+                State::NeedsInput => return Err(VmError::InputExhausted { pc: self.pc }),
+                State::Halted => return Ok(()),
+                _ => continue,
+            }
+        }
+    }
+
+    fn load(&self, addr: Address) -> Result<W, VmError<W>> {
+        if self.profile.is_some() {
+            self.touch(addr);
+        }
+
+        self.tape
+            .read(addr)
+            .map_err(|OutOfBounds(address)| VmError::OutOfBounds {
+                pc: self.pc,
+                address,
+            })
+    }
+
+    fn store(&mut self, addr: Address, value: W) -> Result<(), VmError<W>> {
+        let pc = self.pc;
+
+        if self.profile.is_some() {
+            self.touch(addr);
+        }
+
+        // only writes which succeed are reported
+        let observed = self.observer.as_ref().map(|_| value.clone());
+
+        self.tape
+            .write(addr, value)
+            .map_err(|OutOfBounds(address)| VmError::OutOfBounds { pc, address })?;
+
+        if let (Some(observer), Some(value)) = (&mut self.observer, observed) {
+            observer.write(addr, &value);
         }
+
+        Ok(())
+    }
+}
+
+// The result of evaluating an instruction, for which only its effect on the VM matters.
+type Effect<W> = Result<(), VmError<W>>;
+
+fn binop<W, F>(f: F) -> impl Fn(&mut VM<W>, &[InParam<W>; 2], &OutParam<W>) -> Effect<W>
+where
+    W: Word,
+    F: Fn(&W, &W) -> Option<W>,
+{
+    move |vm: &mut VM<W>, params: &[InParam<W>; 2], out: &OutParam<W>| {
+        let x = params[0].read(vm)?;
+        let y = params[1].read(vm)?;
+        let result = f(&x, &y).ok_or(VmError::Overflow { pc: vm.pc })?;
+
+        out.write(vm, result)
     }
 }
 
-fn binop<F>(f: F) -> impl Fn(&mut [Word], [InParam; 2], OutParam)
+// Jump to the address of the second parameter if the condition holds for the first parameter,
+// otherwise continue with the next instruction.
+fn jump_if<W, F>(f: F) -> impl Fn(&mut VM<W>, &[InParam<W>; 2], usize) -> Effect<W>
 where
-    F: Fn(Word, Word) -> Word,
+    W: Word,
+    F: Fn(&W) -> bool,
 {
-    move |tape: &mut [Word], params: [InParam; 2], out: OutParam| {
-        let x = params[0].read(tape);
-        let y = params[1].read(tape);
+    move |vm: &mut VM<W>, params: &[InParam<W>; 2], len: usize| {
+        let cond = params[0].read(vm)?;
+
+        if f(&cond) {
+            let jump_addr = params[1].read(vm)?;
+            vm.pc = to_address(&jump_addr, vm.pc)?;
+        } else {
+            vm.pc += len;
+        }
 
-        out.write(tape, f(x, y))
+        Ok(())
     }
 }
 
-// fixme: lifetime for<'r> issues
-//fn jump_if<F>(f: F) -> impl Fn(&mut VM, [InParam; 2])
-//where
-//    F: Fn(Word, Word) -> bool,
-//{
-//    move |vm: &mut VM, params: [InParam; 2]| {
-//        let cond = params[0].read(vm.tape);
-//        let jump_addr = params[1].read(vm.tape);
-//
-//        if f(cond, 0) {
-//            vm.pc = jump_addr as usize
-//        }
-//    }
-//}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_bigint::BigInt;
+    use parameterized::parameterized as pm;
+
+    ide!();
+
+    #[pm(
+    program = {
+        &[109, 5, 204, 1, 99, 0, 42],
+        &[109, 7, 203, 0, 204, 0, 99, 0],
+        &[109, 10, 21101, 3, 4, -1, 204, -1, 99, 0, 0],
+        &[109, 4, 109, -2, 22101, 5, 4, 9, 204, 9, 99, 0],
+    },
+    input = {
+        0,
+        17,
+        0,
+        0,
+    },
+    expected = {
+        42,
+        17,
+        7,
+        9,
+    })]
+    fn relative_mode(program: &[i64], input: i64, expected: i64) {
+        let mut vm = VM::with_inputs(program, vec![input]);
+        assert_eq!(
+            vm.execute(ExecutionOption::OutputByTapeOutput),
+            Ok(Outcome::Word(expected))
+        );
+    }
+
+    #[test]
+    fn adjust_relative_base() {
+        let program = vec![109, 19, 109, -4, 209, -9, 99];
+        let mut vm = VM::new(program);
+        vm.execute(ExecutionOption::default()).unwrap();
+
+        // 19 - 4 + tape[15 - 9]
+        assert_eq!(vm.relative_base, 114);
+    }
+
+    #[test]
+    fn quine() {
+        let program = [
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+
+        // writes beyond the program
+        let mut vm = VM::new(program.to_vec());
+        vm.execute(ExecutionOption::default()).unwrap();
+
+        assert_eq!(vm.outputs, program.to_vec());
+    }
+
+    #[test]
+    fn sparse_memory() {
+        // write to, and read back from, a very high address
+        let program = vec![1101, 40, 2, 1 << 40, 4, 1 << 40, 99];
+        let mut vm = VM::<i64>::new(Memory::sparse(program));
+
+        assert_eq!(
+            vm.execute(ExecutionOption::OutputByTapeOutput),
+            Ok(Outcome::Word(42))
+        );
+    }
+
+    #[test]
+    fn large_numbers() {
+        let program = vec![104, 1_125_899_906_842_624, 99];
+        let mut vm = VM::<i64>::new(program);
+        assert_eq!(
+            vm.execute(ExecutionOption::OutputByTapeOutput),
+            Ok(Outcome::Word(1_125_899_906_842_624))
+        );
+
+        let program = vec![1102, 34_915_192, 34_915_192, 7, 4, 7, 99, 0];
+        let mut vm = VM::<i64>::new(program);
+        assert_eq!(
+            vm.execute(ExecutionOption::OutputByTapeOutput),
+            Ok(Outcome::Word(1_219_070_632_396_864))
+        );
+    }
+
+    #[test]
+    fn overflow_is_detected() {
+        let program = vec![1102, 34_915_192, 34_915_192, 7, 4, 7, 99, 0];
+        let mut vm = VM::<i32>::new(program);
+        assert_eq!(
+            vm.execute(ExecutionOption::OutputByTapeOutput),
+            Err(VmError::Overflow { pc: 0 })
+        );
+
+        let program = vec![1101, i64::MAX, 1, 7, 4, 7, 99, 0];
+        let mut vm = VM::<i64>::new(program);
+        assert_eq!(
+            vm.execute(ExecutionOption::OutputByTapeOutput),
+            Err(VmError::Overflow { pc: 0 })
+        );
+    }
+
+    #[test]
+    fn wider_words() {
+        let program = "1102,9223372036854775807,2,7,4,7,99,0";
+
+        let memory = parse::<i64>(program);
+        assert!(VM::new(memory)
+            .execute(ExecutionOption::OutputByTapeOutput)
+            .is_err());
+
+        let memory = parse::<i128>(program);
+        assert_eq!(
+            VM::new(memory).execute(ExecutionOption::OutputByTapeOutput),
+            Ok(Outcome::Word(i128::from(i64::MAX) * 2))
+        );
+
+        let program = "1102,170141183460469231731687303715884105727,2,7,4,7,99,0";
+
+        let memory = parse::<i128>(program);
+        assert!(VM::new(memory)
+            .execute(ExecutionOption::OutputByTapeOutput)
+            .is_err());
+
+        let memory = parse::<BigInt>(program);
+        assert_eq!(
+            VM::new(memory).execute(ExecutionOption::OutputByTapeOutput),
+            Ok(Outcome::Word(BigInt::from(i128::MAX) * 2))
+        );
+    }
+
+    fn parse<W: Word + std::str::FromStr>(program: &str) -> Vec<W> {
+        program
+            .split(',')
+            .map(|v| v.parse().ok().unwrap())
+            .collect()
+    }
+
+    #[pm(
+    program = {
+        &[1, 0, 0, 0, 42],
+        &[304, 0, 99],
+        &[1101, 1, 1, 100, 99],
+        &[1, -1, 0, 0, 99],
+        &[109, -5, 204, 1, 99],
+        &[3, 0, 99],
+        &[11101, 1, 1, 0, 99],
+        &[99],
+    },
+    expected = {
+        VmError::UnknownOpcode { pc: 4, opcode: 42 },
+        VmError::BadParameterMode { pc: 0, opcode: 304, mode: 3 },
+        VmError::OutOfBounds { pc: 0, address: 100 },
+        VmError::NegativeAddress { pc: 0, value: -1 },
+        VmError::NegativeAddress { pc: 2, value: -4 },
+        VmError::InputExhausted { pc: 0 },
+        VmError::WriteInImmediateMode { pc: 0 },
+        VmError::NoOutput { pc: 0 },
+    })]
+    fn errors(program: &[i64], expected: VmError<i64>) {
+        let memory = Memory::new(program.to_vec()).with_limit(64);
+        let mut vm = VM::new(memory);
+
+        let err = vm.execute(ExecutionOption::OutputByTapeOutput).unwrap_err();
+
+        assert_eq!(err.pc(), expected.pc());
+        assert_eq!(err, expected);
+    }
+
+    #[test]
+    fn untaken_jump_to_negative_position() {
+        // [4] is 1, so the target at [-5] is never read
+        let mut vm = VM::new(vec![6, 4, -5, 99, 1]);
+
+        assert_eq!(vm.execute(ExecutionOption::default()), Ok(Outcome::Word(6)));
+    }
+
+    #[test]
+    fn pause_for_input() {
+        // echo inputs forever
+        let program = vec![3, 9, 4, 9, 1105, 1, 0, 99, 0, 0];
+        let mut vm = VM::<i64>::new(program);
+
+        assert_eq!(vm.run(), Ok(State::NeedsInput));
+        assert_eq!(vm.run(), Ok(State::NeedsInput));
+        assert_eq!(vm.pc, 0);
+
+        vm.push_input(5);
+        assert_eq!(vm.run(), Ok(State::Output(5)));
+        assert_eq!(vm.run(), Ok(State::NeedsInput));
+
+        vm.push_input(7);
+        vm.push_input(11);
+        assert_eq!(vm.run(), Ok(State::Output(7)));
+        assert_eq!(vm.run(), Ok(State::Output(11)));
+        assert_eq!(vm.run(), Ok(State::NeedsInput));
+        assert_eq!(vm.outputs, vec![5, 7, 11]);
+    }
+
+    #[test]
+    fn run_until_halted() {
+        // outputs 1 if the input equals 8
+        let program = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
+        let mut vm = VM::<i64>::new(program);
+
+        assert_eq!(vm.run(), Ok(State::NeedsInput));
+        vm.push_input(8);
+        assert_eq!(vm.run(), Ok(State::Output(1)));
+        assert_eq!(vm.run(), Ok(State::Halted));
+        assert_eq!(vm.run(), Ok(State::Halted));
+        assert_eq!(vm.pc, 8);
+    }
+
+    #[test]
+    fn step() {
+        let program = vec![1101, 1, 2, 7, 4, 7, 99, 0];
+        let mut vm = VM::<i64>::new(program);
+
+        assert_eq!(vm.step(), Ok(State::Running));
+        assert_eq!(vm.pc, 4);
+        assert_eq!(vm.tape.read(7), Ok(3));
+        assert_eq!(vm.step(), Ok(State::Output(3)));
+        assert_eq!(vm.step(), Ok(State::Halted));
+    }
+}
@@ -0,0 +1,466 @@
+use crate::vm::{State, VmError, Word, VM};
+use std::fmt::{Display, Formatter};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+
+// A network of VMs, each running on its own thread. Machines exchange words through channels,
+// passing by a coordinator on the calling thread, which knows what each machine is up to.
+//
+//     let mut network = Network::new();
+//     let a = network.add(VM::new(program));
+//     let b = network.add(VM::new(program));
+//     network.connect(a, b);
+//     network.send(a, 1);
+//     let output = network.run()?;
+pub struct Network<W: Word> {
+    machines: Vec<VM<W>>,
+    // where the outputs of each machine go, if anywhere
+    links: Vec<Option<usize>>,
+}
+
+// Something the coordinator wants the caller to know about, see `Network::run_with`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<W: Word> {
+    // a machine which isn't connected to another one produced an output
+    Output { machine: usize, value: W },
+    // all machines which haven't halted are waiting for input
+    Idle { waiting: Vec<usize> },
+}
+
+// What the coordinator should do after an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reaction<W: Word> {
+    Continue,
+    // send words to machines (by index), and continue
+    Send(Vec<(usize, W)>),
+    // stop all machines, and return
+    Stop,
+}
+
+// The outputs of each machine of the network, in emission order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkOutput<W: Word> {
+    pub machines: Vec<Vec<W>>,
+}
+
+// Reasons for a network to stop early.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkError<W: Word> {
+    // a machine failed
+    Machine { machine: usize, error: VmError<W> },
+    // the thread of a machine panicked
+    Panicked { machine: usize },
+    // all machines which haven't halted are waiting for input, which will never arrive
+    Deadlock { waiting: Vec<usize> },
+}
+
+impl<W: Word> Display for NetworkError<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetworkError::Machine { machine, error } => write!(f, "Machine {}: {}", machine, error),
+            NetworkError::Panicked { machine } => write!(f, "Machine {} panicked.", machine),
+            NetworkError::Deadlock { waiting } => {
+                write!(f, "Deadlock, machines {:?} are waiting for input.", waiting)
+            }
+        }
+    }
+}
+
+impl<W: Word> std::error::Error for NetworkError<W> {}
+
+// From the coordinator to a machine.
+enum Command<W> {
+    Input(W),
+}
+
+// From a machine to the coordinator.
+enum Message<W: Word> {
+    Output { machine: usize, value: W },
+    // waiting for input, after having received this many inputs from the coordinator
+    Waiting { machine: usize, received: u64 },
+    Halted { machine: usize },
+    Failed { machine: usize, error: VmError<W> },
+    Panicked { machine: usize },
+}
+
+impl<W: Word> Network<W> {
+    pub fn new() -> Self {
+        Self {
+            machines: Vec::new(),
+            links: Vec::new(),
+        }
+    }
+
+    // Add a machine, which is identified by the returned index.
+    pub fn add(&mut self, vm: VM<W>) -> usize {
+        self.machines.push(vm);
+        self.links.push(None);
+        self.machines.len() - 1
+    }
+
+    // Send the outputs of machine `from` to machine `to`, instead of to the caller.
+    pub fn connect(&mut self, from: usize, to: usize) {
+        self.links[from] = Some(to);
+    }
+
+    // Queue an input for a machine, before the network runs.
+    pub fn send(&mut self, machine: usize, value: W) {
+        self.machines[machine].push_input(value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.machines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty()
+    }
+
+    // Run until all machines halt. Fails when the network deadlocks.
+    pub fn run(self) -> Result<NetworkOutput<W>, NetworkError<W>> {
+        self.run_with(|_| Reaction::Continue)
+    }
+
+    // Run until all machines halt, or `on_event` stops the network. When the network is idle,
+    // and `on_event` doesn't send any words, the network is deadlocked.
+    //
+    // Either way, all machines are stopped and their threads joined before returning.
+    pub fn run_with<F>(self, mut on_event: F) -> Result<NetworkOutput<W>, NetworkError<W>>
+    where
+        F: FnMut(Event<W>) -> Reaction<W>,
+    {
+        let len = self.machines.len();
+        let stop = Arc::new(AtomicBool::new(false));
+        let (events, messages) = channel();
+
+        let (commands, threads): (Vec<_>, Vec<_>) = self
+            .machines
+            .into_iter()
+            .enumerate()
+            .map(|(machine, vm)| {
+                let (commands, inbox) = channel();
+                let events = events.clone();
+                let stop = stop.clone();
+
+                let thread = std::thread::spawn(move || {
+                    let run = || run_machine(machine, vm, inbox, events.clone(), stop);
+
+                    if std::panic::catch_unwind(AssertUnwindSafe(run)).is_err() {
+                        let _ = events.send(Message::Panicked { machine });
+                    }
+                });
+
+                (commands, thread)
+            })
+            .unzip();
+        drop(events);
+
+        let mut coordinator = Coordinator {
+            commands,
+            links: self.links,
+            outputs: vec![Vec::new(); len],
+            sent: vec![0; len],
+            waiting: vec![None; len],
+            halted: vec![false; len],
+        };
+
+        let result = coordinator.coordinate(&messages, &mut on_event);
+        let Coordinator {
+            commands, outputs, ..
+        } = coordinator;
+
+        // stop the machines which are still running, and wake those waiting for input
+        stop.store(true, Ordering::Relaxed);
+        drop(commands);
+
+        for thread in threads {
+            let _ = thread.join();
+        }
+
+        result.map(|()| NetworkOutput { machines: outputs })
+    }
+}
+
+impl<W: Word> Default for Network<W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Coordinator<W: Word> {
+    commands: Vec<Sender<Command<W>>>,
+    links: Vec<Option<usize>>,
+    outputs: Vec<Vec<W>>,
+    // the number of inputs sent to each machine
+    sent: Vec<u64>,
+    // the number of inputs each machine had received when it last reported to wait
+    waiting: Vec<Option<u64>>,
+    halted: Vec<bool>,
+}
+
+impl<W: Word> Coordinator<W> {
+    fn coordinate<F>(
+        &mut self,
+        messages: &Receiver<Message<W>>,
+        on_event: &mut F,
+    ) -> Result<(), NetworkError<W>>
+    where
+        F: FnMut(Event<W>) -> Reaction<W>,
+    {
+        while !self.halted.iter().all(|&halted| halted) {
+            // machines only hang up after they told why, so this doesn't happen
+            let message = match messages.recv() {
+                Ok(message) => message,
+                Err(_) => return Ok(()),
+            };
+
+            match message {
+                Message::Output { machine, value } => {
+                    self.outputs[machine].push(value.clone());
+
+                    match self.links[machine] {
+                        Some(to) => self.deliver(to, value),
+                        None => {
+                            if !self.react(on_event(Event::Output { machine, value })) {
+                                return Ok(());
+                            }
+                        }
+                    }
+                }
+                Message::Waiting { machine, received } => self.waiting[machine] = Some(received),
+                Message::Halted { machine } => self.halted[machine] = true,
+                Message::Failed { machine, error } => {
+                    return Err(NetworkError::Machine { machine, error })
+                }
+                Message::Panicked { machine } => return Err(NetworkError::Panicked { machine }),
+            }
+
+            let waiting = self.idle();
+            if waiting.is_empty() {
+                continue;
+            }
+
+            match on_event(Event::Idle {
+                waiting: waiting.clone(),
+            }) {
+                Reaction::Stop => return Ok(()),
+                Reaction::Send(values) if !values.is_empty() => {
+                    self.react(Reaction::Send(values));
+                }
+                // nothing will get the network going again
+                _ => return Err(NetworkError::Deadlock { waiting }),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Whether to continue.
+    fn react(&mut self, reaction: Reaction<W>) -> bool {
+        match reaction {
+            Reaction::Continue => true,
+            Reaction::Send(values) => {
+                values
+                    .into_iter()
+                    .for_each(|(machine, value)| self.deliver(machine, value));
+                true
+            }
+            Reaction::Stop => false,
+        }
+    }
+
+    // Inputs for machines which halted are dropped.
+    fn deliver(&mut self, machine: usize, value: W) {
+        if !self.halted[machine] && self.commands[machine].send(Command::Input(value)).is_ok() {
+            self.sent[machine] += 1;
+        }
+    }
+
+    // The machines which wait for input, if all machines either wait or halted; none otherwise.
+    // A machine only waits if it received everything which was sent to it.
+    fn idle(&self) -> Vec<usize> {
+        let waiting = |machine: usize| self.waiting[machine] == Some(self.sent[machine]);
+
+        let idle = (0..self.halted.len()).all(|machine| self.halted[machine] || waiting(machine));
+        if !idle {
+            return Vec::new();
+        }
+
+        (0..self.halted.len())
+            .filter(|&machine| !self.halted[machine])
+            .collect()
+    }
+}
+
+fn run_machine<W: Word>(
+    machine: usize,
+    mut vm: VM<W>,
+    inbox: Receiver<Command<W>>,
+    events: Sender<Message<W>>,
+    stop: Arc<AtomicBool>,
+) {
+    let mut received = 0;
+
+    while !stop.load(Ordering::Relaxed) {
+        let message = match vm.step() {
+            Ok(State::Running) => continue,
+            Ok(State::Output(value)) => Message::Output { machine, value },
+            Ok(State::NeedsInput) => {
+                let command = match inbox.try_recv() {
+                    Ok(command) => Ok(command),
+                    Err(TryRecvError::Empty) => {
+                        let _ = events.send(Message::Waiting { machine, received });
+                        inbox.recv().map_err(|_| ())
+                    }
+                    Err(TryRecvError::Disconnected) => Err(()),
+                };
+
+                match command {
+                    Ok(Command::Input(value)) => {
+                        received += 1;
+                        vm.push_input(value);
+                        continue;
+                    }
+                    // the coordinator is done
+                    Err(()) => return,
+                }
+            }
+            Ok(State::Halted) => {
+                let _ = events.send(Message::Halted { machine });
+                return;
+            }
+            Err(error) => {
+                let _ = events.send(Message::Failed { machine, error });
+                return;
+            }
+        };
+
+        let _ = events.send(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::assemble;
+
+    ide!();
+
+    // adds one to each input, until it reads a zero
+    fn incrementer() -> Vec<i64> {
+        assemble(
+            "
+            loop:   in [x]
+                    jf [x], #end
+                    add [x], #1, [x]
+                    out [x]
+                    jt #1, #loop
+            end:    hlt
+            x:      data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn deadlock() {
+        let mut network = Network::new();
+        let machines = (0..3)
+            .map(|_| network.add(VM::new(incrementer())))
+            .collect::<Vec<_>>();
+        network.connect(machines[0], machines[1]);
+        network.connect(machines[1], machines[2]);
+
+        network.send(0, 10);
+        network.send(0, 20);
+
+        // all of them wait for more
+        assert_eq!(
+            network.run(),
+            Err(NetworkError::Deadlock { waiting: machines })
+        );
+    }
+
+    #[test]
+    fn halts() {
+        let mut network = Network::new();
+        let a = network.add(VM::with_inputs(incrementer(), vec![1, 2, 0]));
+        let b = network.add(VM::with_inputs(vec![104, 42, 99], vec![]));
+
+        let output = network.run().unwrap();
+        assert_eq!(output.machines[a], vec![2, 3]);
+        assert_eq!(output.machines[b], vec![42]);
+    }
+
+    #[test]
+    fn feedback_loop() {
+        // the amplifier program of the day 7 example, with its phase settings
+        let program = [
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+
+        let mut network = Network::new();
+        for phase in [9, 8, 7, 6, 5].iter() {
+            network.add(VM::with_inputs(&program[..], vec![*phase]));
+        }
+        for n in 0..5 {
+            network.connect(n, (n + 1) % 5);
+        }
+        network.send(0, 0);
+
+        let output = network.run().unwrap();
+        assert_eq!(output.machines[4].last(), Some(&139_629_729));
+    }
+
+    #[test]
+    fn idle_handler() {
+        // feeds the output of the machine back to it, whenever the network is idle
+        let mut network = Network::new();
+        network.add(VM::with_inputs(incrementer(), vec![1]));
+
+        let mut last = None;
+        let output = network
+            .run_with(|event| match event {
+                Event::Output { value, .. } => {
+                    last = Some(value);
+                    Reaction::Continue
+                }
+                Event::Idle { waiting } => match last {
+                    Some(value) if value < 5 => Reaction::Send(vec![(waiting[0], value)]),
+                    _ => Reaction::Stop,
+                },
+            })
+            .unwrap();
+
+        assert_eq!(output.machines, vec![vec![2, 3, 4, 5]]);
+    }
+
+    #[test]
+    fn failure() {
+        let mut network = Network::new();
+        network.add(VM::new(incrementer()));
+        network.add(VM::new(vec![42]));
+
+        assert_eq!(
+            network.run(),
+            Err(NetworkError::Machine {
+                machine: 1,
+                error: VmError::UnknownOpcode { pc: 0, opcode: 42 }
+            })
+        );
+    }
+
+    #[test]
+    fn stops_running_machines() {
+        // the second machine spins forever
+        let mut network = Network::new();
+        network.add(VM::with_inputs(vec![104, 1, 99], vec![]));
+        network.add(VM::new(vec![1105, 1, 0]));
+
+        let output = network.run_with(|_| Reaction::Stop).unwrap();
+        assert_eq!(output.machines, vec![vec![1], vec![]]);
+    }
+}
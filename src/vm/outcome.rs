@@ -0,0 +1,140 @@
+use crate::vm::{Address, Memory, VmError, Word};
+
+// The result of `VM::execute`, depending on the `ExecutionOption`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome<W: Word> {
+    Word(W),
+    Outputs(Vec<W>),
+    Run(RunResult<W>),
+}
+
+impl<W: Word> Outcome<W> {
+    pub fn word(self) -> Option<W> {
+        match self {
+            Outcome::Word(word) => Some(word),
+            _ => None,
+        }
+    }
+
+    pub fn outputs(self) -> Option<Vec<W>> {
+        match self {
+            Outcome::Outputs(outputs) => Some(outputs),
+            _ => None,
+        }
+    }
+
+    pub fn run(self) -> Option<RunResult<W>> {
+        match self {
+            Outcome::Run(run) => Some(run),
+            _ => None,
+        }
+    }
+}
+
+// Everything about a finished execution.
+#[derive(Debug, Clone)]
+pub struct RunResult<W: Word> {
+    // the final memory
+    pub memory: Memory<W>,
+    pub halt: Halt<W>,
+    // all outputs, in emission order
+    pub outputs: Vec<W>,
+}
+
+// Memories compare by their words.
+impl<W: Word> PartialEq for RunResult<W> {
+    fn eq(&self, other: &Self) -> bool {
+        self.halt == other.halt
+            && self.outputs == other.outputs
+            && self.memory.segments() == other.memory.segments()
+    }
+}
+
+impl<W: Word> Eq for RunResult<W> {}
+
+// Why the execution ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Halt<W: Word> {
+    // at the halting instruction at pc
+    Halted { pc: Address },
+    Failed(VmError<W>),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::{assemble, ExecutionOption, Halt, Outcome, VmError, VM};
+    use parameterized::parameterized as pm;
+
+    ide!();
+
+    // outputs 1, 2, 3, stores 42 at result, and halts
+    fn program() -> Vec<i64> {
+        assemble(
+            "
+                    out #1
+                    out #2
+                    out #3
+                    add #40, #2, [result]
+                    hlt
+            result: data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    #[pm(option = {
+        ExecutionOption::OutputByAddress(11),
+        ExecutionOption::OutputByTapeOutput,
+        ExecutionOption::AllOutputs,
+        ExecutionOption::LastOutputs(2),
+        ExecutionOption::LastOutputs(5),
+        ExecutionOption::LastOutputs(0),
+    }, expected = {
+        Outcome::Word(42),
+        Outcome::Word(3),
+        Outcome::Outputs(vec![1, 2, 3]),
+        Outcome::Outputs(vec![2, 3]),
+        Outcome::Outputs(vec![1, 2, 3]),
+        Outcome::Outputs(vec![]),
+    })]
+    fn options(option: ExecutionOption, expected: Outcome<i64>) {
+        let mut vm = VM::new(program());
+
+        assert_eq!(vm.execute(option), Ok(expected));
+    }
+
+    #[test]
+    fn run_result() {
+        let mut vm = VM::new(program());
+        let run = vm
+            .execute(ExecutionOption::RunResult)
+            .unwrap()
+            .run()
+            .unwrap();
+
+        assert_eq!(run.halt, Halt::Halted { pc: 10 });
+        assert_eq!(run.outputs, vec![1, 2, 3]);
+        assert_eq!(run.memory.read(11), Ok(42));
+    }
+
+    #[test]
+    fn run_result_of_failure() {
+        // outputs, then wants an input
+        let mut vm = VM::new(vec![104, 7, 3, 0, 99]);
+        let run = vm
+            .execute(ExecutionOption::RunResult)
+            .unwrap()
+            .run()
+            .unwrap();
+
+        assert_eq!(run.halt, Halt::Failed(VmError::InputExhausted { pc: 2 }));
+        assert_eq!(run.outputs, vec![7]);
+
+        // other options fail
+        let mut vm = VM::new(vec![104, 7, 3, 0, 99]);
+        assert_eq!(
+            vm.execute(ExecutionOption::AllOutputs),
+            Err(VmError::InputExhausted { pc: 2 })
+        );
+    }
+}
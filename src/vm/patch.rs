@@ -0,0 +1,196 @@
+use crate::vm::{Address, Instruction, Observer, Word};
+use std::collections::{BTreeSet, HashSet};
+
+// A write to an address which was executed before, as (part of) an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodePatch<W: Word> {
+    // the instruction which wrote
+    pub pc: Address,
+    pub address: Address,
+    pub value: W,
+}
+
+// Counts of the writes seen by a `PatchDetector`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PatchStats {
+    // writes to addresses which were executed before
+    pub code_writes: usize,
+    // all other writes
+    pub data_writes: usize,
+    // distinct addresses among the code writes
+    pub patched_addresses: usize,
+}
+
+// Detects self-modifying code: tells writes to code, from writes to data. An address is code
+// once an instruction covering it (the opcode or any of its parameters) was executed; writes to
+// addresses which are only executed afterwards count as data writes.
+//
+// Attach it with `VM::with_observer`, behind an `Arc<Mutex<_>>` to inspect it afterwards.
+#[derive(Debug, Clone)]
+pub struct PatchDetector<W: Word> {
+    executed: HashSet<Address>,
+    patches: Vec<CodePatch<W>>,
+    data_writes: usize,
+
+    // the instruction being executed
+    pc: Address,
+}
+
+impl<W: Word> PatchDetector<W> {
+    pub fn new() -> Self {
+        Self {
+            executed: HashSet::new(),
+            patches: Vec::new(),
+            data_writes: 0,
+            pc: 0,
+        }
+    }
+
+    // The writes to code, in order.
+    pub fn patches(&self) -> &[CodePatch<W>] {
+        &self.patches
+    }
+
+    // The addresses which were written to after being executed.
+    pub fn patched_addresses(&self) -> BTreeSet<Address> {
+        self.patches.iter().map(|patch| patch.address).collect()
+    }
+
+    pub fn is_executed(&self, address: Address) -> bool {
+        self.executed.contains(&address)
+    }
+
+    pub fn stats(&self) -> PatchStats {
+        PatchStats {
+            code_writes: self.patches.len(),
+            data_writes: self.data_writes,
+            patched_addresses: self.patched_addresses().len(),
+        }
+    }
+}
+
+impl<W: Word> Default for PatchDetector<W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: Word> Observer<W> for PatchDetector<W> {
+    fn instruction(&mut self, pc: Address, instruction: &Instruction<W>, _params: &[Option<W>]) {
+        self.pc = pc;
+        self.executed.extend(pc..pc + instruction.len());
+    }
+
+    fn write(&mut self, address: Address, value: &W) {
+        if self.executed.contains(&address) {
+            self.patches.push(CodePatch {
+                pc: self.pc,
+                address,
+                value: value.clone(),
+            });
+        } else {
+            self.data_writes += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{assemble, ExecutionOption, VM};
+    use std::sync::{Arc, Mutex};
+
+    ide!();
+
+    fn detect(program: Vec<i64>) -> PatchDetector<i64> {
+        let detector = Arc::new(Mutex::new(PatchDetector::new()));
+        let mut vm = VM::new(program).with_observer(detector.clone());
+        vm.execute(ExecutionOption::default()).unwrap();
+        drop(vm);
+
+        Arc::try_unwrap(detector).unwrap().into_inner().unwrap()
+    }
+
+    #[test]
+    fn patches() {
+        // the loop turns its own add into a mul after the first iteration, and counts in data
+        let program = assemble(
+            "
+            loop:   add [x], [x], [x]
+                    add #2, #0, [loop]
+                    add #-1, [n], [n]
+                    jt [n], #loop
+                    hlt
+            x:      data 3
+            n:      data 2
+            ",
+        )
+        .unwrap();
+
+        let detector = detect(program);
+
+        assert_eq!(
+            detector.patches(),
+            &[
+                CodePatch {
+                    pc: 4,
+                    address: 0,
+                    value: 2
+                },
+                CodePatch {
+                    pc: 4,
+                    address: 0,
+                    value: 2
+                },
+            ]
+        );
+        assert_eq!(
+            detector.stats(),
+            PatchStats {
+                code_writes: 2,
+                data_writes: 4,
+                patched_addresses: 1,
+            }
+        );
+        assert!(detector.is_executed(3));
+        assert!(!detector.is_executed(17));
+    }
+
+    #[test]
+    fn parameters_are_code() {
+        // stores into the parameter of an instruction which already ran
+        let program = assemble(
+            "
+                    add #1, #1, [1]
+                    hlt
+            ",
+        )
+        .unwrap();
+
+        let detector = detect(program);
+
+        assert_eq!(
+            detector.patched_addresses().into_iter().collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert_eq!(detector.stats().data_writes, 0);
+    }
+
+    #[test]
+    fn future_code_is_data() {
+        // writes the halting instruction before reaching it
+        let program = assemble(
+            "
+                    add #99, #0, [end]
+            end:    data 0
+            ",
+        )
+        .unwrap();
+
+        let detector = detect(program);
+
+        assert!(detector.patches().is_empty());
+        assert_eq!(detector.stats().data_writes, 1);
+        assert!(detector.is_executed(4));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::vm::{Address, Instruction, State, VmError, Word, VM};
+use std::collections::BTreeMap;
+
+// Execution statistics of a VM, collected while it runs. Enable with `VM::with_profiling`.
+//
+// Input instructions which have to wait for input aren't counted until they're executed.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Profile {
+    // the number of executed instructions
+    pub steps: u64,
+    // executed instructions, by mnemonic
+    pub by_opcode: BTreeMap<&'static str, u64>,
+    // executed instructions, by address
+    pub by_address: BTreeMap<Address, u64>,
+    // the highest address which was executed, read or written, if any
+    pub max_address: Option<Address>,
+    // taken backward jumps, by (target, address of the jump)
+    backward_jumps: BTreeMap<(Address, Address), u64>,
+}
+
+// A loop, closed by a backward jump from `end` to `start`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Loop {
+    pub start: Address,
+    pub end: Address,
+    // the number of times the jump was taken
+    pub iterations: u64,
+}
+
+impl Profile {
+    // The loops which were iterated, most iterated first.
+    pub fn hot_loops(&self) -> Vec<Loop> {
+        let mut loops = self
+            .backward_jumps
+            .iter()
+            .map(|(&(start, end), &iterations)| Loop {
+                start,
+                end,
+                iterations,
+            })
+            .collect::<Vec<_>>();
+
+        loops.sort_by(|a, b| b.iterations.cmp(&a.iterations).then(a.start.cmp(&b.start)));
+        loops
+    }
+
+    // The instruction at `pc` was executed, and jumped back to `target` if it's a taken backward
+    // jump. `touched` is the highest address it accessed.
+    fn record(
+        &mut self,
+        pc: Address,
+        mnemonic: &'static str,
+        target: Option<Address>,
+        touched: Address,
+    ) {
+        self.steps += 1;
+        *self.by_opcode.entry(mnemonic).or_insert(0) += 1;
+        *self.by_address.entry(pc).or_insert(0) += 1;
+        self.max_address = self.max_address.max(Some(touched));
+
+        if let Some(target) = target {
+            *self.backward_jumps.entry((target, pc)).or_insert(0) += 1;
+        }
+    }
+}
+
+impl<W: Word> VM<W> {
+    // Collect a `Profile` of the execution from now on.
+    pub fn with_profiling(mut self) -> Self {
+        self.profile = Some(Profile::default());
+        self
+    }
+
+    // The profile collected so far, if profiling is enabled.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    // Execute the fetched instruction like `eval`, and record it in the profile.
+    pub(crate) fn eval_profiled(
+        &mut self,
+        instruction: &Instruction<W>,
+    ) -> Result<State<W>, VmError<W>> {
+        let pc = self.pc;
+        // the instruction touches its own words, and whatever it reads and writes
+        self.touched.set(pc.saturating_add(instruction.len() - 1));
+
+        let state = instruction.eval(self)?;
+        let touched = self.touched.get();
+
+        let target = Some(self.pc).filter(|&next| instruction.is_jump() && next <= pc);
+
+        if state != State::NeedsInput {
+            if let Some(profile) = &mut self.profile {
+                profile.record(pc, instruction.mnemonic(), target, touched);
+            }
+        }
+
+        Ok(state)
+    }
+
+    // The address is read or written by the current instruction.
+    pub(crate) fn touch(&self, address: Address) {
+        self.touched.set(self.touched.get().max(address));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{assemble, ExecutionOption, Outcome};
+
+    ide!();
+
+    // sums 1..=n, where n is the input
+    fn program() -> Vec<i64> {
+        assemble(
+            "
+                    in [n]
+            loop:   add [n], [sum], [sum]
+                    add #-1, [n], [n]
+                    jt [n], #loop
+                    out [sum]
+                    hlt
+            n:      data 0
+            sum:    data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn profile() {
+        let mut vm = VM::with_inputs(program(), vec![4]).with_profiling();
+        assert_eq!(
+            vm.execute(ExecutionOption::OutputByTapeOutput),
+            Ok(Outcome::Word(10))
+        );
+
+        let profile = vm.profile().unwrap();
+
+        assert_eq!(profile.steps, 1 + 4 * 3 + 2);
+        assert_eq!(
+            profile.by_opcode.iter().collect::<Vec<_>>(),
+            vec![
+                (&"add", &8),
+                (&"hlt", &1),
+                (&"in", &1),
+                (&"jt", &4),
+                (&"out", &1)
+            ]
+        );
+        assert_eq!(profile.by_address.get(&2), Some(&4));
+        assert_eq!(profile.by_address.get(&15), Some(&1));
+        assert_eq!(profile.max_address, Some(17));
+        assert_eq!(
+            profile.hot_loops(),
+            vec![Loop {
+                start: 2,
+                end: 10,
+                iterations: 3
+            }]
+        );
+    }
+
+    #[test]
+    fn waiting_for_input_is_not_counted() {
+        let mut vm = VM::new(program()).with_profiling();
+        vm.run().unwrap();
+        vm.run().unwrap();
+        assert_eq!(vm.profile().map(|profile| profile.steps), Some(0));
+
+        vm.push_input(1);
+        vm.execute(ExecutionOption::OutputByTapeOutput).unwrap();
+        assert_eq!(vm.take_profile().map(|profile| profile.steps), Some(6));
+        assert_eq!(vm.profile(), None);
+    }
+
+    #[test]
+    fn relative_addresses() {
+        // reads rb+1000 with rb = 10
+        let mut vm = VM::new(vec![109, 10, 204, 1000, 99]).with_profiling();
+        vm.execute(ExecutionOption::OutputByTapeOutput).unwrap();
+
+        assert_eq!(vm.profile().unwrap().max_address, Some(1010));
+    }
+
+    #[test]
+    fn untaken_jump_target_is_not_touched() {
+        // [4] is 1, so the target at [1000] is never read
+        let mut vm = VM::new(vec![6, 4, 1000, 99, 1]).with_profiling();
+        vm.execute(ExecutionOption::default()).unwrap();
+
+        assert_eq!(vm.profile().unwrap().max_address, Some(4));
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let mut vm = VM::with_inputs(program(), vec![2]);
+        vm.execute(ExecutionOption::OutputByTapeOutput).unwrap();
+
+        assert_eq!(vm.profile(), None);
+    }
+}
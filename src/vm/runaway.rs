@@ -0,0 +1,203 @@
+use crate::vm::{Address, Instruction, VmError, Word, VM};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+// Guards against programs which run forever, e.g. day 2 with a bad noun and verb.
+impl<W: Word> VM<W> {
+    // Fail with `StepLimitExceeded` instead of executing more than `limit` instructions in total.
+    // Halting doesn't count, so a halted VM stays halted.
+    pub fn with_step_limit(mut self, limit: u64) -> Self {
+        self.step_limit = Some(limit);
+        self
+    }
+
+    // Fail with `InfiniteLoop` when the VM returns to exactly the same state: the same pc,
+    // relative base and memory, without having read an input in between.
+    //
+    // A state can only repeat after jumping back, so only those are checked; but each check
+    // hashes all of the memory, so this slows down loops considerably.
+    pub fn with_loop_detection(mut self) -> Self {
+        self.visited = Some(HashSet::new());
+        self
+    }
+
+    // The number of instructions executed so far, not counting the halting instruction.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // The instruction at `pc` was just executed.
+    pub(crate) fn detect_loop(
+        &mut self,
+        pc: Address,
+        instruction: &Instruction<W>,
+    ) -> Result<(), VmError<W>> {
+        let repeated = match instruction {
+            // the input may differ on the next pass, so earlier states don't count anymore
+            Instruction::Input(_) => {
+                self.forget_visited();
+                false
+            }
+            instruction if instruction.is_jump() && self.pc <= pc => {
+                let state = self.state_hash();
+                self.visited
+                    .as_mut()
+                    .is_some_and(|seen| !seen.insert(state))
+            }
+            _ => false,
+        };
+
+        if repeated {
+            Err(VmError::InfiniteLoop { pc })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn forget_visited(&mut self) {
+        if let Some(visited) = &mut self.visited {
+            visited.clear();
+        }
+    }
+
+    fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        self.pc.hash(&mut hasher);
+        self.relative_base.hash(&mut hasher);
+        self.tape.hash_words(&mut hasher);
+
+        hasher.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{assemble, ExecutionOption, Outcome, State};
+    use parameterized::parameterized as pm;
+
+    ide!();
+
+    // counts down from the input to zero, and halts
+    fn countdown() -> Vec<i64> {
+        assemble(
+            "
+                    in [n]
+            loop:   add #-1, [n], [n]
+                    jt [n], #loop
+                    hlt
+            n:      data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    #[pm(limit = { 6, 7 }, expected = {
+        Err(VmError::StepLimitExceeded { pc: 6, limit: 6 }),
+        Ok(Outcome::Word(3)),
+    })]
+    fn step_limit(limit: u64, expected: Result<Outcome<i64>, VmError<i64>>) {
+        // in, 3 * (add, jt), and hlt isn't counted
+        let mut vm = VM::with_inputs(countdown(), vec![3]).with_step_limit(limit);
+
+        assert_eq!(vm.execute(ExecutionOption::default()), expected);
+        assert_eq!(vm.steps(), limit);
+    }
+
+    #[test]
+    fn step_limit_for_spinning_program() {
+        let mut vm = VM::new(vec![1105, 1, 0]).with_step_limit(1000);
+
+        assert_eq!(
+            vm.execute(ExecutionOption::default()),
+            Err(VmError::StepLimitExceeded { pc: 0, limit: 1000 })
+        );
+    }
+
+    #[test]
+    fn halted_stays_halted() {
+        let mut vm = VM::new(vec![99]).with_step_limit(1);
+
+        for _ in 0..3 {
+            assert_eq!(vm.run(), Ok(State::Halted));
+        }
+        assert_eq!(vm.steps(), 0);
+    }
+
+    #[pm(program = {
+        vec![1105, 1, 0],
+        // jumps back and forth
+        vec![1105, 1, 3, 1105, 1, 0],
+        // toggles a flag, so only the second pass repeats
+        assemble("
+            loop:   eq [flag], #0, [flag]
+                    jt #1, #loop
+            flag:   data 0
+        ").unwrap(),
+    }, pc = { 0, 3, 4 })]
+    fn infinite_loop(program: Vec<i64>, pc: Address) {
+        let mut vm = VM::new(program).with_loop_detection();
+
+        assert_eq!(
+            vm.execute(ExecutionOption::default()),
+            Err(VmError::InfiniteLoop { pc })
+        );
+    }
+
+    #[test]
+    fn terminating_loop() {
+        let mut vm = VM::with_inputs(countdown(), vec![100]).with_loop_detection();
+
+        assert_eq!(vm.execute(ExecutionOption::default()), Ok(Outcome::Word(3)));
+    }
+
+    #[test]
+    fn restore_resets_detection() {
+        // counts down without reading an input, so nothing else resets the detection
+        let program = assemble(
+            "
+            loop:   add #-1, [n], [n]
+                    jt [n], #loop
+                    hlt
+            n:      data 100
+            ",
+        )
+        .unwrap();
+        let mut vm = VM::new(program).with_loop_detection().with_step_limit(250);
+        let snapshot = vm.snapshot();
+
+        for _ in 0..2 {
+            assert_eq!(
+                vm.execute(ExecutionOption::OutputByAddress(8)),
+                Ok(Outcome::Word(0))
+            );
+            assert_eq!(vm.steps(), 200);
+
+            vm.restore(&snapshot);
+        }
+    }
+
+    #[test]
+    fn inputs_reset_detection() {
+        // echoes its inputs forever, with the same memory for equal inputs
+        let program = assemble(
+            "
+            loop:   in [x]
+                    out [x]
+                    jt #1, #loop
+            x:      data 0
+            ",
+        )
+        .unwrap();
+        let mut vm = VM::new(program).with_loop_detection();
+
+        for _ in 0..3 {
+            vm.push_input(5);
+            assert_eq!(vm.run(), Ok(State::Output(5)));
+        }
+
+        assert_eq!(vm.run(), Ok(State::NeedsInput));
+    }
+}
@@ -0,0 +1,315 @@
+use crate::vm::{Word, VM};
+use std::cmp::Ordering;
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
+use std::sync::Mutex;
+
+// Searches a space of inputs for a program, by evaluating each input on a fresh VM, on several
+// threads. The threads take the inputs one by one, so a slow input doesn't hold up the others.
+//
+// The results don't depend on the number of threads: the first match, or the best value, is the
+// one which comes first in the order of the inputs.
+#[derive(Debug, Clone)]
+pub struct Search<W: Word> {
+    program: Vec<W>,
+    threads: usize,
+}
+
+// What a thread found for the inputs it took. Inputs are numbered in order.
+struct Found<X, T, E> {
+    best: Option<(usize, X, T)>,
+    error: Option<(usize, E)>,
+}
+
+impl<X, T, E> Default for Found<X, T, E> {
+    fn default() -> Self {
+        Self {
+            best: None,
+            error: None,
+        }
+    }
+}
+
+impl<W: Word> Search<W> {
+    // Use as many threads as there are cores.
+    pub fn new(program: &[W]) -> Self {
+        Self {
+            program: program.to_vec(),
+            threads: std::thread::available_parallelism().map_or(1, usize::from),
+        }
+    }
+
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    // The first input for which `evaluate` finds something. Once it's found, later inputs are
+    // skipped.
+    pub fn find<I, T, F>(&self, inputs: I, evaluate: F) -> Option<(I::Item, T)>
+    where
+        I: IntoIterator,
+        I::IntoIter: Send,
+        I::Item: Send,
+        T: Send,
+        F: Fn(VM<W>, &I::Item) -> Option<T> + Sync,
+    {
+        // the index of the first match so far
+        let first = AtomicUsize::new(usize::MAX);
+
+        self.spread(inputs, |found: &mut Found<_, _, ()>, index, input, vm| {
+            if index > first.load(atomic::Ordering::Relaxed) {
+                return false;
+            }
+
+            match evaluate(vm, &input) {
+                Some(value) => {
+                    first.fetch_min(index, atomic::Ordering::Relaxed);
+                    found.best = Some((index, input, value));
+                    // the inputs of this thread only come later
+                    false
+                }
+                None => true,
+            }
+        })
+        .into_iter()
+        .filter_map(|found| found.best)
+        .min_by_key(|&(index, _, _)| index)
+        .map(|(_, input, value)| (input, value))
+    }
+
+    // The input with the largest value, which is None when there are no inputs. The search stops
+    // at the first error.
+    pub fn max<I, T, E, F>(&self, inputs: I, evaluate: F) -> Result<Option<(I::Item, T)>, E>
+    where
+        I: IntoIterator,
+        I::IntoIter: Send,
+        I::Item: Send,
+        T: Ord + Send,
+        E: Send,
+        F: Fn(VM<W>, &I::Item) -> Result<T, E> + Sync,
+    {
+        self.best(inputs, evaluate, Ordering::Greater)
+    }
+
+    // The input with the smallest value, like `max`.
+    pub fn min<I, T, E, F>(&self, inputs: I, evaluate: F) -> Result<Option<(I::Item, T)>, E>
+    where
+        I: IntoIterator,
+        I::IntoIter: Send,
+        I::Item: Send,
+        T: Ord + Send,
+        E: Send,
+        F: Fn(VM<W>, &I::Item) -> Result<T, E> + Sync,
+    {
+        self.best(inputs, evaluate, Ordering::Less)
+    }
+
+    // The input whose value compares as `better` to all others.
+    fn best<I, T, E, F>(
+        &self,
+        inputs: I,
+        evaluate: F,
+        better: Ordering,
+    ) -> Result<Option<(I::Item, T)>, E>
+    where
+        I: IntoIterator,
+        I::IntoIter: Send,
+        I::Item: Send,
+        T: Ord + Send,
+        E: Send,
+        F: Fn(VM<W>, &I::Item) -> Result<T, E> + Sync,
+    {
+        let failed = AtomicBool::new(false);
+
+        let found = self.spread(inputs, |found: &mut Found<_, _, _>, index, input, vm| {
+            if failed.load(atomic::Ordering::Relaxed) {
+                return false;
+            }
+
+            match evaluate(vm, &input) {
+                Ok(value) => {
+                    // on a tie, the earlier input stays
+                    if found
+                        .best
+                        .as_ref()
+                        .is_none_or(|(_, _, best)| value.cmp(best) == better)
+                    {
+                        found.best = Some((index, input, value));
+                    }
+
+                    true
+                }
+                Err(error) => {
+                    failed.store(true, atomic::Ordering::Relaxed);
+                    found.error = Some((index, error));
+                    false
+                }
+            }
+        });
+
+        let mut best: Option<(usize, I::Item, T)> = None;
+        let mut error: Option<(usize, E)> = None;
+
+        for found in found {
+            if let Some((index, e)) = found.error {
+                if error.as_ref().is_none_or(|&(first, _)| index < first) {
+                    error = Some((index, e));
+                }
+            }
+
+            if let Some((index, input, value)) = found.best {
+                let replace =
+                    best.as_ref()
+                        .is_none_or(|(best_index, _, best)| match value.cmp(best) {
+                            Ordering::Equal => index < *best_index,
+                            ordering => ordering == better,
+                        });
+
+                if replace {
+                    best = Some((index, input, value));
+                }
+            }
+        }
+
+        match error {
+            Some((_, error)) => Err(error),
+            None => Ok(best.map(|(_, input, value)| (input, value))),
+        }
+    }
+
+    // Give each input, with its index, to `work` on some thread, together with a fresh VM. Each
+    // thread collects what it found in its own `A`; it stops once `work` returns false.
+    fn spread<I, A, F>(&self, inputs: I, work: F) -> Vec<A>
+    where
+        I: IntoIterator,
+        I::IntoIter: Send,
+        I::Item: Send,
+        A: Default + Send,
+        F: Fn(&mut A, usize, I::Item, VM<W>) -> bool + Sync,
+    {
+        let inputs = Mutex::new(inputs.into_iter().enumerate());
+
+        std::thread::scope(|scope| {
+            let workers = (0..self.threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        // each input forks from the loaded program, and only copies the memory
+                        // pages it changes
+                        let base = VM::new(self.program.as_slice());
+                        let mut found = A::default();
+
+                        // a poisoned lock means the inputs panicked on another thread; that panic
+                        // is raised when the thread is joined
+                        while let Some((index, input)) =
+                            inputs.lock().ok().and_then(|mut inputs| inputs.next())
+                        {
+                            if !work(&mut found, index, input, base.fork()) {
+                                break;
+                            }
+                        }
+
+                        found
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            workers
+                .into_iter()
+                .map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{assemble, ExecutionOption, VmError};
+    use parameterized::parameterized as pm;
+
+    ide!();
+
+    // outputs the square of the input
+    fn square() -> Vec<i64> {
+        assemble(
+            "
+                in [x]
+                mul [x], [x], [x]
+                out [x]
+                hlt
+            x:  data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    fn run(mut vm: VM<i64>, input: i64) -> Result<i64, VmError<i64>> {
+        vm.push_input(input);
+        vm.execute(ExecutionOption::OutputByTapeOutput)
+            .map(|outcome| outcome.word().unwrap())
+    }
+
+    #[pm(threads = { 1, 2, 3, 16 })]
+    fn find_first(threads: usize) {
+        let search = Search::new(&square()).with_threads(threads);
+
+        let found = search.find(0..1000, |vm, &x| {
+            run(vm, x).ok().filter(|&square| square > 2000)
+        });
+        assert_eq!(found, Some((45, 2025)));
+
+        assert_eq!(
+            search.find(0..10, |vm, &x| run(vm, x).ok().filter(|_| false)),
+            None
+        );
+    }
+
+    #[test]
+    fn find_skips_later_inputs() {
+        let evaluated = AtomicUsize::new(0);
+        let search = Search::new(&square()).with_threads(1);
+
+        let found = search.find(0.., |vm, &x| {
+            evaluated.fetch_add(1, atomic::Ordering::Relaxed);
+            run(vm, x).ok().filter(|&square| square == 100)
+        });
+
+        assert_eq!(found, Some((10, 100)));
+        assert_eq!(evaluated.into_inner(), 11);
+    }
+
+    #[pm(threads = { 1, 2, 3, 16 })]
+    fn reductions(threads: usize) {
+        let search = Search::new(&square()).with_threads(threads);
+
+        assert_eq!(
+            search.max(-20..10, |vm, &x| run(vm, x)),
+            Ok(Some((-20, 400)))
+        );
+        // on a tie, the earlier input wins
+        assert_eq!(search.max(-9..=9, |vm, &x| run(vm, x)), Ok(Some((-9, 81))));
+        assert_eq!(search.min(-9..=9, |vm, &x| run(vm, x)), Ok(Some((0, 0))));
+        assert_eq!(search.min(0..0, |vm, &x| run(vm, x)), Ok(None));
+    }
+
+    #[test]
+    fn errors_stop_the_search() {
+        let search = Search::new(&square()).with_threads(4);
+
+        // overflows for large inputs
+        let max = search.max(vec![1, 2, 1 << 40, 3], |vm, &x| run(vm, x));
+        assert_eq!(max, Err(VmError::Overflow { pc: 2 }));
+
+        // the evaluator decides what's an error
+        let min = search.min(0..100, |vm, &x| match run(vm, x) {
+            Ok(_) if x == 50 => Err("fifty"),
+            Ok(square) => Ok(square),
+            Err(_) => Err("vm"),
+        });
+        assert_eq!(min, Err("fifty"));
+    }
+}
@@ -0,0 +1,138 @@
+use crate::vm::{Address, Memory, Word, VM};
+use std::collections::VecDeque;
+
+// The state of a VM at some point of its execution, which can be restored later, or forked into
+// independent VMs. Memory pages are shared until written to, so snapshots are cheap to take.
+#[derive(Debug, Clone)]
+pub struct Snapshot<W: Word> {
+    pub tape: Memory<W>,
+    pub pc: Address,
+    pub relative_base: W,
+    pub inputs: VecDeque<W>,
+    pub outputs: VecDeque<W>,
+}
+
+impl<W: Word> VM<W> {
+    pub fn snapshot(&self) -> Snapshot<W> {
+        Snapshot {
+            tape: self.tape.clone(),
+            pc: self.pc,
+            relative_base: self.relative_base.clone(),
+            inputs: self.inputs.clone(),
+            outputs: self.outputs.clone(),
+        }
+    }
+
+    // Go back to the state of the snapshot. The observer, profile, and attached input and
+    // output, if any, are kept. The step count starts over, and loop detection forgets the states
+    // it has seen.
+    pub fn restore(&mut self, snapshot: &Snapshot<W>) {
+        let snapshot = snapshot.clone();
+
+        self.steps = 0;
+        self.forget_visited();
+
+        self.tape = snapshot.tape;
+        self.pc = snapshot.pc;
+        self.relative_base = snapshot.relative_base;
+        self.inputs = snapshot.inputs;
+        self.outputs = snapshot.outputs;
+    }
+
+    // An independent copy of this VM, which continues from the same state. The observer, profile,
+    // and attached input and output aren't copied.
+    pub fn fork(&self) -> VM<W> {
+        self.snapshot().into()
+    }
+}
+
+impl<W: Word> From<Snapshot<W>> for VM<W> {
+    fn from(snapshot: Snapshot<W>) -> Self {
+        let mut vm = VM::with_memory(snapshot.tape);
+
+        vm.pc = snapshot.pc;
+        vm.relative_base = snapshot.relative_base;
+        vm.inputs = snapshot.inputs;
+        vm.outputs = snapshot.outputs;
+        vm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{assemble, ExecutionOption, Outcome, State};
+
+    ide!();
+
+    // adds its inputs together, until it reads a zero
+    fn program() -> Vec<i64> {
+        assemble(
+            "
+            loop:   in [x]
+                    jf [x], #end
+                    add [x], [sum], [sum]
+                    out [sum]
+                    jt #1, #loop
+            end:    hlt
+            x:      data 0
+            sum:    data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn restore() {
+        let mut vm = VM::with_inputs(program(), vec![1, 2]);
+        assert_eq!(vm.run(), Ok(State::Output(1)));
+
+        let snapshot = vm.snapshot();
+        assert_eq!(vm.run(), Ok(State::Output(3)));
+        assert_eq!(vm.run(), Ok(State::NeedsInput));
+
+        vm.restore(&snapshot);
+        assert_eq!(vm.pc, snapshot.pc);
+        assert_eq!(vm.outputs, vec![1]);
+        assert_eq!(vm.inputs, vec![2]);
+        assert_eq!(vm.run(), Ok(State::Output(3)));
+    }
+
+    #[test]
+    fn fork() {
+        let mut vm = VM::with_inputs(program(), vec![10]);
+        assert_eq!(vm.run(), Ok(State::Output(10)));
+
+        // branch from the shared prefix with different inputs
+        let sums = (1..=3)
+            .map(|n| {
+                let mut fork = vm.fork();
+                fork.inputs.extend(vec![n, 0]);
+                fork.execute(ExecutionOption::OutputByTapeOutput).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            sums,
+            vec![Outcome::Word(11), Outcome::Word(12), Outcome::Word(13)]
+        );
+
+        // the original is unaffected
+        assert_eq!(vm.run(), Ok(State::NeedsInput));
+        assert_eq!(vm.outputs, vec![10]);
+    }
+
+    #[test]
+    fn snapshot_into_vm() {
+        let mut vm = VM::with_inputs(program(), vec![4, 5]);
+        vm.run().unwrap();
+
+        let mut resumed = VM::from(vm.snapshot());
+        resumed.push_input(0);
+
+        assert_eq!(
+            resumed.execute(ExecutionOption::OutputByTapeOutput),
+            Ok(Outcome::Word(9))
+        );
+    }
+}
@@ -0,0 +1,326 @@
+use crate::vm::memory::DENSE_LIMIT;
+use crate::vm::{Address, Memory, Snapshot, Word};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+use std::path::Path;
+
+// Version of the saved state format; bumped on incompatible changes.
+const VERSION: u32 = 1;
+
+// How a saved VM is encoded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    // human readable, for inspecting and editing a state, or attaching it to a bug report
+    Json,
+    // compact, the bincode 1.x encoding (little endian, fixed width integers) of the same structure
+    Binary,
+}
+
+// The saved state of a VM. As JSON, it looks like:
+//
+//     {
+//       "version": 1,
+//       "pc": 4,
+//       "relative_base": 0,
+//       "inputs": [7],
+//       "outputs": [1, 2],
+//       "memory": {
+//         "sparse": false,
+//         "limit": 16777216,
+//         "segments": [{ "start": 0, "words": [3, 9, 4, 9, 99, 0, 0, 0, 0, 5] }]
+//       }
+//     }
+//
+// * words are plain numbers for the primitive word types; `BigInt` words use the encoding of
+//   num-bigint
+// * outputs are in emission order
+// * memory is saved up to its length, as runs of consecutive words; addresses which aren't part
+//   of any segment read as zero
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound(serialize = "W: Serialize", deserialize = "W: DeserializeOwned"))]
+struct SavedState<W> {
+    version: u32,
+    pc: Address,
+    relative_base: W,
+    inputs: Vec<W>,
+    outputs: Vec<W>,
+    memory: SavedMemory<W>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound(serialize = "W: Serialize", deserialize = "W: DeserializeOwned"))]
+struct SavedMemory<W> {
+    sparse: bool,
+    limit: usize,
+    segments: Vec<Segment<W>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound(serialize = "W: Serialize", deserialize = "W: DeserializeOwned"))]
+struct Segment<W> {
+    start: Address,
+    words: Vec<W>,
+}
+
+// Reasons a state can't be saved or loaded.
+#[derive(Debug)]
+pub enum StateError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Binary(bincode::Error),
+    // the state was saved in a format version we don't understand
+    UnsupportedVersion(u32),
+    // a memory segment lies beyond the memory limit of the state
+    OutOfBounds(Address),
+    // the limit of dense memory is beyond what dense memory supports; states may come from bug
+    // reports, so they mustn't make us allocate arbitrary amounts of memory
+    LimitTooLarge(usize),
+}
+
+impl Display for StateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StateError::Io(error) => write!(f, "Unable to access state: {}.", error),
+            StateError::Json(error) => write!(f, "Invalid JSON state: {}.", error),
+            StateError::Binary(error) => write!(f, "Invalid binary state: {}.", error),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported state version {}.", version)
+            }
+            StateError::OutOfBounds(address) => {
+                write!(f, "Memory of the state is out of bounds at {}.", address)
+            }
+            StateError::LimitTooLarge(limit) => write!(
+                f,
+                "Memory limit {} of the state is too large for dense memory.",
+                limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<std::io::Error> for StateError {
+    fn from(error: std::io::Error) -> Self {
+        StateError::Io(error)
+    }
+}
+
+impl<W> Snapshot<W>
+where
+    W: Word + Serialize + DeserializeOwned,
+{
+    pub fn write_to<T: Write>(&self, writer: T, format: Format) -> Result<(), StateError> {
+        let state = SavedState {
+            version: VERSION,
+            pc: self.pc,
+            relative_base: self.relative_base.clone(),
+            inputs: self.inputs.iter().cloned().collect(),
+            outputs: self.outputs.iter().cloned().collect(),
+            memory: SavedMemory {
+                sparse: self.tape.is_sparse(),
+                limit: self.tape.limit(),
+                segments: self
+                    .tape
+                    .segments()
+                    .into_iter()
+                    .map(|(start, words)| Segment { start, words })
+                    .collect(),
+            },
+        };
+
+        match format {
+            Format::Json => serde_json::to_writer_pretty(writer, &state).map_err(StateError::Json),
+            Format::Binary => bincode::serialize_into(writer, &state).map_err(StateError::Binary),
+        }
+    }
+
+    pub fn read_from<R: Read>(reader: R, format: Format) -> Result<Self, StateError> {
+        let state: SavedState<W> = match format {
+            Format::Json => serde_json::from_reader(reader).map_err(StateError::Json)?,
+            Format::Binary => bincode::deserialize_from(reader).map_err(StateError::Binary)?,
+        };
+
+        if state.version != VERSION {
+            return Err(StateError::UnsupportedVersion(state.version));
+        }
+
+        if !state.memory.sparse && state.memory.limit > DENSE_LIMIT {
+            return Err(StateError::LimitTooLarge(state.memory.limit));
+        }
+
+        let segments = state
+            .memory
+            .segments
+            .into_iter()
+            .map(|segment| (segment.start, segment.words))
+            .collect();
+        let tape = Memory::from_segments(state.memory.sparse, state.memory.limit, segments)
+            .map_err(|error| StateError::OutOfBounds(error.0))?;
+
+        Ok(Snapshot {
+            tape,
+            pc: state.pc,
+            relative_base: state.relative_base,
+            inputs: state.inputs.into_iter().collect(),
+            outputs: state.outputs.into_iter().collect(),
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: Format) -> Result<(), StateError> {
+        let file = std::fs::File::create(path)?;
+        self.write_to(std::io::BufWriter::new(file), format)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P, format: Format) -> Result<Self, StateError> {
+        let file = std::fs::File::open(path)?;
+        Self::read_from(std::io::BufReader::new(file), format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{ExecutionOption, Outcome, State, VM};
+    use num_bigint::BigInt;
+    use parameterized::parameterized as pm;
+
+    ide!();
+
+    // echoes its inputs, doubled, until it reads a zero
+    const PROGRAM: [i64; 16] = [
+        3, 15, 1006, 15, 14, 1002, 15, 2, 15, 4, 15, 1105, 1, 0, 99, 0,
+    ];
+
+    fn paused() -> VM<i64> {
+        let mut vm = VM::with_inputs(&PROGRAM[..], vec![1, 2, 3]);
+        vm.run().unwrap();
+        vm.run().unwrap();
+        vm
+    }
+
+    fn round_trip<W>(snapshot: &Snapshot<W>, format: Format) -> Snapshot<W>
+    where
+        W: Word + Serialize + DeserializeOwned,
+    {
+        let mut bytes = Vec::new();
+        snapshot.write_to(&mut bytes, format).unwrap();
+
+        Snapshot::read_from(&bytes[..], format).unwrap()
+    }
+
+    #[pm(format = { Format::Json, Format::Binary })]
+    fn resume_after_load(format: Format) {
+        let vm = paused();
+        let mut loaded = VM::from(round_trip(&vm.snapshot(), format));
+
+        assert_eq!(loaded.pc, vm.pc);
+        assert_eq!(loaded.inputs, vm.inputs);
+        assert_eq!(loaded.outputs, vm.outputs);
+        assert_eq!(loaded.tape.segments(), vm.tape.segments());
+
+        loaded.push_input(0);
+        assert_eq!(loaded.run(), Ok(State::Output(6)));
+        assert_eq!(loaded.run(), Ok(State::Halted));
+        assert_eq!(loaded.outputs, vec![2, 4, 6]);
+    }
+
+    #[test]
+    fn json_format() {
+        let mut vm = VM::with_inputs(vec![3, 9, 4, 9, 99, 0, 0, 0, 0, 5], vec![7, 8]);
+        vm.run().unwrap();
+        vm.relative_base = -3;
+
+        let mut json = Vec::new();
+        vm.snapshot().write_to(&mut json, Format::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": 1,
+                "pc": 4,
+                "relative_base": -3,
+                "inputs": [8],
+                "outputs": [7],
+                "memory": {
+                    "sparse": false,
+                    "limit": 16_777_216,
+                    "segments": [{ "start": 0, "words": [3, 9, 4, 9, 99, 0, 0, 0, 0, 7] }]
+                }
+            })
+        );
+    }
+
+    #[pm(format = { Format::Json, Format::Binary })]
+    fn sparse_and_wide(format: Format) {
+        let mut memory = Memory::sparse(vec![BigInt::from(104), BigInt::from(1) << 100, 99.into()]);
+        memory.write(1 << 40, BigInt::from(-1)).unwrap();
+
+        let mut vm = VM::new(memory);
+        let snapshot = round_trip(&vm.snapshot(), format);
+
+        assert!(snapshot.tape.is_sparse());
+        assert_eq!(snapshot.tape.len(), (1 << 40) + 1);
+        assert_eq!(snapshot.tape.read(1 << 40), Ok(BigInt::from(-1)));
+
+        vm.restore(&snapshot);
+        assert_eq!(
+            vm.execute(ExecutionOption::OutputByTapeOutput),
+            Ok(Outcome::Word(BigInt::from(1) << 100))
+        );
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("aoc19-state-{}.json", std::process::id()));
+        let snapshot = paused().snapshot();
+
+        snapshot.save(&path, Format::Json).unwrap();
+        let loaded = Snapshot::<i64>::load(&path, Format::Json).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.pc, snapshot.pc);
+        assert_eq!(loaded.tape.segments(), snapshot.tape.segments());
+    }
+
+    #[pm(json = {
+        r#"{"version": 2, "pc": 0, "relative_base": 0, "inputs": [], "outputs": [],
+            "memory": {"sparse": false, "limit": 10, "segments": []}}"#,
+        r#"{"version": 1, "pc": 0, "relative_base": 0, "inputs": [], "outputs": [],
+            "memory": {"sparse": false, "limit": 10, "segments": [{"start": 8, "words": [1, 2, 3]}]}}"#,
+        r#"{"version": 1, "pc": 0}"#,
+    })]
+    fn invalid(json: &str) {
+        let error = Snapshot::<i64>::read_from(json.as_bytes(), Format::Json).unwrap_err();
+
+        match error {
+            StateError::UnsupportedVersion(2)
+            | StateError::OutOfBounds(10)
+            | StateError::Json(_) => {}
+            error => panic!("unexpected error: {}", error),
+        }
+    }
+
+    #[pm(sparse = { false, true })]
+    fn huge_limit(sparse: bool) {
+        let json = format!(
+            r#"{{"version": 1, "pc": 0, "relative_base": 0, "inputs": [], "outputs": [],
+                "memory": {{"sparse": {}, "limit": 18446744073709551615,
+                           "segments": [{{"start": 1099511627776, "words": [1]}}]}}}}"#,
+            sparse
+        );
+
+        match Snapshot::<i64>::read_from(json.as_bytes(), Format::Json) {
+            // sparse memory only stores the words which are used
+            Ok(snapshot) => {
+                assert!(sparse);
+                assert_eq!(snapshot.tape.read(1 << 40), Ok(1));
+            }
+            Err(StateError::LimitTooLarge(usize::MAX)) => assert!(!sparse),
+            Err(error) => panic!("unexpected error: {}", error),
+        }
+    }
+}
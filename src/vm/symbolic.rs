@@ -0,0 +1,564 @@
+use crate::vm::{
+    to_address, Address, BinopInstr, InParam, Instruction, Memory, OutParam, VmError, Word,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+
+// A polynomial over the symbols x0, x1, .., with integer coefficients.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Polynomial<W: Word> {
+    // the coefficients by the exponents of the symbols, without trailing zero exponents;
+    // coefficients are never zero
+    terms: BTreeMap<Vec<u32>, W>,
+}
+
+impl<W: Word> Polynomial<W> {
+    pub fn constant(value: W) -> Self {
+        let mut terms = BTreeMap::new();
+        if !value.is_zero() {
+            terms.insert(Vec::new(), value);
+        }
+
+        Self { terms }
+    }
+
+    pub fn symbol(symbol: usize) -> Self {
+        let mut exponents = vec![0; symbol + 1];
+        exponents[symbol] = 1;
+
+        let mut terms = BTreeMap::new();
+        terms.insert(exponents, W::one());
+
+        Self { terms }
+    }
+
+    // The value, if the polynomial doesn't depend on any symbol.
+    pub fn as_constant(&self) -> Option<W> {
+        match self.terms.iter().next() {
+            None => Some(W::zero()),
+            Some((exponents, value)) if exponents.is_empty() && self.terms.len() == 1 => {
+                Some(value.clone())
+            }
+            Some(_) => None,
+        }
+    }
+
+    // None on overflow.
+    pub fn checked_add(&self, other: &Self) -> Option<Self> {
+        let mut sum = self.clone();
+        for (exponents, value) in &other.terms {
+            sum.add_term(exponents.clone(), value)?;
+        }
+
+        Some(sum)
+    }
+
+    // None on overflow.
+    pub fn checked_mul(&self, other: &Self) -> Option<Self> {
+        let mut product = Self::constant(W::zero());
+        for (exponents, value) in &self.terms {
+            for (other_exponents, other_value) in &other.terms {
+                let (long, short) = if exponents.len() >= other_exponents.len() {
+                    (exponents, other_exponents)
+                } else {
+                    (other_exponents, exponents)
+                };
+
+                let mut exponents = long.clone();
+                exponents
+                    .iter_mut()
+                    .zip(short)
+                    .for_each(|(exponent, other)| *exponent += other);
+
+                product.add_term(exponents, &value.checked_mul(other_value)?)?;
+            }
+        }
+
+        Some(product)
+    }
+
+    // The highest power of the symbol.
+    pub fn degree(&self, symbol: usize) -> u32 {
+        self.terms
+            .keys()
+            .map(|exponents| exponents.get(symbol).cloned().unwrap_or(0))
+            .max()
+            .unwrap_or(0)
+    }
+
+    // The polynomial as a sum of `coefficients[i] * symbol^i`, where the coefficients don't
+    // depend on the symbol.
+    pub fn coefficients(&self, symbol: usize) -> Vec<Self> {
+        let mut coefficients = vec![Self::constant(W::zero()); self.degree(symbol) as usize + 1];
+
+        for (exponents, value) in &self.terms {
+            let mut exponents = exponents.clone();
+            let power = exponents
+                .get_mut(symbol)
+                .map_or(0, |power| std::mem::replace(power, 0) as usize);
+
+            normalize(&mut exponents);
+            coefficients[power].terms.insert(exponents, value.clone());
+        }
+
+        coefficients
+    }
+
+    // The value for the values of the symbols, or None on overflow. Symbols without a value are
+    // taken as 0.
+    pub fn eval(&self, symbols: &[W]) -> Option<W> {
+        let mut sum = W::zero();
+
+        for (exponents, value) in &self.terms {
+            let mut term = value.clone();
+
+            for (symbol, &exponent) in exponents.iter().enumerate() {
+                let symbol = symbols.get(symbol).cloned().unwrap_or_else(W::zero);
+
+                for _ in 0..exponent {
+                    term = term.checked_mul(&symbol)?;
+                }
+            }
+
+            sum = sum.checked_add(&term)?;
+        }
+
+        Some(sum)
+    }
+
+    fn add_term(&mut self, exponents: Vec<u32>, value: &W) -> Option<()> {
+        let sum = match self.terms.get(&exponents) {
+            Some(existing) => existing.checked_add(value)?,
+            None => value.clone(),
+        };
+
+        if sum.is_zero() {
+            self.terms.remove(&exponents);
+        } else {
+            self.terms.insert(exponents, sum);
+        }
+
+        Some(())
+    }
+}
+
+fn normalize(exponents: &mut Vec<u32>) {
+    while exponents.last() == Some(&0) {
+        exponents.pop();
+    }
+}
+
+// Highest powers first, e.g. `3*x0^2*x1 + x1 + 5`
+impl<W: Word> Display for Polynomial<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.terms.is_empty() {
+            return write!(f, "0");
+        }
+
+        for (i, (exponents, value)) in self.terms.iter().rev().enumerate() {
+            if i > 0 {
+                write!(f, " + ")?;
+            }
+
+            let symbols = exponents
+                .iter()
+                .enumerate()
+                .filter(|&(_, &exponent)| exponent > 0)
+                .map(|(symbol, &exponent)| match exponent {
+                    1 => format!("x{}", symbol),
+                    _ => format!("x{}^{}", symbol, exponent),
+                })
+                .collect::<Vec<_>>();
+
+            match (value.is_one(), symbols.is_empty()) {
+                (_, true) => write!(f, "{}", value)?,
+                (true, false) => write!(f, "{}", symbols.join("*"))?,
+                (false, false) => write!(f, "{}*{}", value, symbols.join("*"))?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// The content of a memory cell, during symbolic execution.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value<W: Word> {
+    Known(Polynomial<W>),
+    // read from an address which depends on the symbols
+    Unknown,
+}
+
+impl<W: Word> Value<W> {
+    pub fn constant(&self) -> Option<W> {
+        match self {
+            Value::Known(polynomial) => polynomial.as_constant(),
+            Value::Unknown => None,
+        }
+    }
+}
+
+// Why a program can't be executed symbolically.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolicError<W: Word> {
+    // the program jumps on a condition, or to an address, which depends on the symbols
+    Branches { pc: Address },
+    // an instruction, or where it writes to, depends on the symbols
+    SymbolicCode { pc: Address },
+    // input and output can't be executed symbolically
+    Unsupported { pc: Address },
+    Vm(VmError<W>),
+}
+
+impl<W: Word> From<VmError<W>> for SymbolicError<W> {
+    fn from(error: VmError<W>) -> Self {
+        SymbolicError::Vm(error)
+    }
+}
+
+impl<W: Word> Display for SymbolicError<W> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SymbolicError::Branches { pc } => {
+                write!(f, "Control flow depends on the symbols (pc: {}).", pc)
+            }
+            SymbolicError::SymbolicCode { pc } => {
+                write!(f, "Instruction depends on the symbols (pc: {}).", pc)
+            }
+            SymbolicError::Unsupported { pc } => {
+                write!(
+                    f,
+                    "Instruction can't be executed symbolically (pc: {}).",
+                    pc
+                )
+            }
+            SymbolicError::Vm(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl<W: Word> std::error::Error for SymbolicError<W> {}
+
+// Executes a program where some words are symbols instead of numbers, so the results are
+// polynomials over the symbols. This only works as long as the control flow doesn't depend on
+// the symbols, e.g. for straight-line programs.
+pub struct Symbolic<W: Word> {
+    // the concrete words; cells with a value which isn't constant hold 0
+    memory: Memory<W>,
+    // the values which aren't constant
+    symbolic: HashMap<Address, Value<W>>,
+    pc: Address,
+    relative_base: W,
+    steps: u64,
+    step_limit: Option<u64>,
+}
+
+impl<W: Word> Symbolic<W> {
+    // The words at the addresses are replaced by the symbols x0, x1, .., in order.
+    pub fn new(program: &[W], symbols: &[Address]) -> Self {
+        let mut symbolic = Self {
+            memory: Memory::new(program.to_vec()),
+            symbolic: HashMap::new(),
+            pc: 0,
+            relative_base: W::zero(),
+            steps: 0,
+            step_limit: None,
+        };
+
+        for (symbol, &address) in symbols.iter().enumerate() {
+            symbolic
+                .symbolic
+                .insert(address, Value::Known(Polynomial::symbol(symbol)));
+        }
+
+        symbolic
+    }
+
+    // Like `VM::with_step_limit`.
+    pub fn with_step_limit(mut self, limit: u64) -> Self {
+        self.step_limit = Some(limit);
+        self
+    }
+
+    pub fn value(&self, address: Address) -> Result<Value<W>, VmError<W>> {
+        match self.symbolic.get(&address) {
+            Some(value) => Ok(value.clone()),
+            None => self
+                .memory
+                .read(address)
+                .map(|value| Value::Known(Polynomial::constant(value)))
+                .map_err(|_| VmError::OutOfBounds {
+                    pc: self.pc,
+                    address,
+                }),
+        }
+    }
+
+    // Run the program until it halts.
+    pub fn run(&mut self) -> Result<(), SymbolicError<W>> {
+        loop {
+            let pc = self.pc;
+            if self.symbolic.contains_key(&pc) {
+                return Err(SymbolicError::SymbolicCode { pc });
+            }
+
+            let instruction = Instruction::fetch(&self.memory, pc)?;
+            if instruction == Instruction::Ret {
+                return Ok(());
+            }
+
+            if let Some(limit) = self.step_limit {
+                if self.steps >= limit {
+                    return Err(VmError::StepLimitExceeded { pc, limit }.into());
+                }
+            }
+            self.steps += 1;
+
+            // the words of the instruction, after the opcode
+            let words = (pc + 1..pc + instruction.len())
+                .map(|address| self.value(address))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let read = |this: &Self, n: usize| this.read(&instruction.params()[n], &words[n]);
+
+            match &instruction {
+                Instruction::Binop(..) | Instruction::LessThan(..) | Instruction::Equals(..) => {
+                    let (x, y) = (read(self, 0)?, read(self, 1)?);
+
+                    let value = match (&instruction, &x, &y) {
+                        (_, Value::Unknown, _) | (_, _, Value::Unknown) => Value::Unknown,
+                        (Instruction::Binop(op, ..), Value::Known(x), Value::Known(y)) => {
+                            let value = match op {
+                                BinopInstr::Add => x.checked_add(y),
+                                BinopInstr::Mul => x.checked_mul(y),
+                            };
+
+                            Value::Known(value.ok_or(VmError::Overflow { pc })?)
+                        }
+                        _ => match (x.constant(), y.constant()) {
+                            (Some(x), Some(y)) => {
+                                let holds = match instruction {
+                                    Instruction::LessThan(..) => x < y,
+                                    _ => x == y,
+                                };
+
+                                let value = if holds { W::one() } else { W::zero() };
+                                Value::Known(Polynomial::constant(value))
+                            }
+                            _ => Value::Unknown,
+                        },
+                    };
+
+                    self.write(pc, &instruction, &words[2], value)?;
+                }
+                Instruction::JumpIfTrue(_) | Instruction::JumpIfFalse(_) => {
+                    let condition = read(self, 0)?.constant();
+                    let condition = condition.ok_or(SymbolicError::Branches { pc })?;
+
+                    let taken = match instruction {
+                        Instruction::JumpIfTrue(_) => !condition.is_zero(),
+                        _ => condition.is_zero(),
+                    };
+
+                    if taken {
+                        let target = read(self, 1)?.constant();
+                        let target = target.ok_or(SymbolicError::Branches { pc })?;
+                        self.pc = to_address(&target, pc)?;
+                        continue;
+                    }
+                }
+                Instruction::AdjustRelativeBase(_) => {
+                    let adjustment = read(self, 0)?.constant();
+                    let adjustment = adjustment.ok_or(SymbolicError::SymbolicCode { pc })?;
+
+                    self.relative_base = self
+                        .relative_base
+                        .checked_add(&adjustment)
+                        .ok_or(VmError::Overflow { pc })?;
+                }
+                Instruction::Input(_) | Instruction::Output(_) => {
+                    return Err(SymbolicError::Unsupported { pc })
+                }
+                Instruction::Ret => unreachable!("halting is handled above"),
+            }
+
+            self.pc += instruction.len();
+        }
+    }
+
+    // The value of the parameter, whose word in the instruction is `word`.
+    fn read(&self, param: &InParam<W>, word: &Value<W>) -> Result<Value<W>, SymbolicError<W>> {
+        let address = match (param, word.constant()) {
+            (InParam::Immediate(_), _) => return Ok(word.clone()),
+            // the address depends on the symbols
+            (_, None) => return Ok(Value::Unknown),
+            (InParam::Position(address), Some(_)) => to_address(address, self.pc)?,
+            (InParam::Relative(offset), Some(_)) => self.relative_address(offset)?,
+        };
+
+        Ok(self.value(address)?)
+    }
+
+    // Write the value to the output parameter of the instruction, whose word is `word`.
+    fn write(
+        &mut self,
+        pc: Address,
+        instruction: &Instruction<W>,
+        word: &Value<W>,
+        value: Value<W>,
+    ) -> Result<(), SymbolicError<W>> {
+        let out = match instruction {
+            Instruction::Binop(_, _, out)
+            | Instruction::LessThan(_, out)
+            | Instruction::Equals(_, out) => out,
+            _ => unreachable!("only binary operations are written"),
+        };
+
+        if word.constant().is_none() {
+            return Err(SymbolicError::SymbolicCode { pc });
+        }
+
+        let address = match out {
+            OutParam::Position(address) => to_address(address, pc)?,
+            OutParam::Relative(offset) => self.relative_address(offset)?,
+        };
+
+        let concrete = value.constant();
+        self.memory
+            .write(address, concrete.clone().unwrap_or_else(W::zero))
+            .map_err(|_| VmError::OutOfBounds { pc, address })?;
+
+        match concrete {
+            Some(_) => self.symbolic.remove(&address),
+            None => self.symbolic.insert(address, value),
+        };
+
+        Ok(())
+    }
+
+    fn relative_address(&self, offset: &W) -> Result<Address, VmError<W>> {
+        let address = self
+            .relative_base
+            .checked_add(offset)
+            .ok_or(VmError::Overflow { pc: self.pc })?;
+
+        to_address(&address, self.pc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::assemble;
+    use parameterized::parameterized as pm;
+
+    ide!();
+
+    fn x(symbol: usize) -> Polynomial<i64> {
+        Polynomial::symbol(symbol)
+    }
+
+    fn c(value: i64) -> Polynomial<i64> {
+        Polynomial::constant(value)
+    }
+
+    #[test]
+    fn polynomials() {
+        // (x0 + 2) * (x0 + x1)
+        let p = x(0)
+            .checked_add(&c(2))
+            .unwrap()
+            .checked_mul(&x(0).checked_add(&x(1)).unwrap())
+            .unwrap();
+
+        assert_eq!(p.to_string(), "x0^2 + x0*x1 + 2*x0 + 2*x1");
+        assert_eq!(p.eval(&[3, 4]), Some(35));
+        assert_eq!(p.degree(0), 2);
+        assert_eq!(p.degree(1), 1);
+        assert_eq!(p.as_constant(), None);
+
+        let by_x1 = p.coefficients(1);
+        assert_eq!(
+            by_x1
+                .iter()
+                .map(|coefficient| coefficient.to_string())
+                .collect::<Vec<_>>(),
+            vec!["x0^2 + 2*x0", "x0 + 2"]
+        );
+
+        // terms cancel out
+        let zero = p.checked_add(&p.checked_mul(&c(-1)).unwrap()).unwrap();
+        assert_eq!(zero.as_constant(), Some(0));
+        assert_eq!(zero.to_string(), "0");
+    }
+
+    #[test]
+    fn straight_line() {
+        // x0 * 3 + x1, where the symbols are the first words after the opcodes
+        let program = assemble::<i64>(
+            "
+                mul #0, #3, [t]
+                add [t], #0, [0]
+                hlt
+            t:  data 0
+            ",
+        )
+        .unwrap();
+
+        let mut symbolic = Symbolic::new(&program, &[1, 6]);
+        symbolic.run().unwrap();
+
+        assert_eq!(
+            symbolic.value(0).unwrap(),
+            Value::Known(x(0).checked_mul(&c(3)).unwrap().checked_add(&x(1)).unwrap())
+        );
+        assert_eq!(symbolic.value(9).unwrap().constant(), None);
+    }
+
+    #[test]
+    fn symbolic_addresses() {
+        // reads from the address x0, and overwrites the result
+        let program = vec![1, 0, 0, 9, 1101, 0, 5, 9, 99, 0];
+
+        let mut symbolic = Symbolic::new(&program, &[1]);
+        symbolic.run().unwrap();
+
+        // [9] was unknown, then 5
+        assert_eq!(symbolic.value(9).unwrap().constant(), Some(5));
+    }
+
+    #[pm(program = {
+        vec![1005, 0, 0, 99],
+        vec![1, 0, 0, 0, 99],
+        vec![1, 0, 0, 4, 99],
+        vec![3, 0, 99],
+    }, symbols = {
+        vec![1],
+        vec![3],
+        vec![1],
+        vec![],
+    }, expected = {
+        SymbolicError::Branches { pc: 0 },
+        SymbolicError::SymbolicCode { pc: 0 },
+        SymbolicError::SymbolicCode { pc: 4 },
+        SymbolicError::Unsupported { pc: 0 },
+    })]
+    fn unsupported(program: Vec<i64>, symbols: Vec<Address>, expected: SymbolicError<i64>) {
+        let mut symbolic = Symbolic::new(&program, &symbols);
+
+        assert_eq!(symbolic.run(), Err(expected));
+    }
+
+    #[test]
+    fn step_limit() {
+        let mut symbolic = Symbolic::new(&[1105, 1, 0], &[]).with_step_limit(10);
+
+        assert_eq!(
+            symbolic.run(),
+            Err(SymbolicError::Vm(VmError::StepLimitExceeded {
+                pc: 0,
+                limit: 10
+            }))
+        );
+    }
+}
@@ -0,0 +1,297 @@
+use crate::vm::{Address, Instruction, Word};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+// Watches a VM while it runs. Attach one with `VM::with_observer`.
+pub trait Observer<W: Word> {
+    // An instruction was fetched at `pc`, and is about to be executed. `params` holds the values
+    // of the parameters which are read by the instruction, in order. A value is None when it
+    // can't be read, e.g. the target of a jump which isn't taken; observing never fails the VM.
+    //
+    // An input instruction which has to wait for input is reported again when it's retried.
+    fn instruction(&mut self, _pc: Address, _instruction: &Instruction<W>, _params: &[Option<W>]) {}
+
+    // The value was written to memory, at `address`. Writes which fail aren't reported.
+    fn write(&mut self, _address: Address, _value: &W) {}
+}
+
+// Shared, so the observer can still be inspected while (or after) the VM owns it.
+impl<W: Word, O: Observer<W>> Observer<W> for Arc<Mutex<O>> {
+    fn instruction(&mut self, pc: Address, instruction: &Instruction<W>, params: &[Option<W>]) {
+        if let Ok(mut observer) = self.lock() {
+            observer.instruction(pc, instruction, params);
+        }
+    }
+
+    fn write(&mut self, address: Address, value: &W) {
+        if let Ok(mut observer) = self.lock() {
+            observer.write(address, value);
+        }
+    }
+}
+
+// Writes a human-readable line for each instruction and each memory write:
+//
+//     4: add [9], #3, [9]         ; 5, 3
+//        [9] <- 8
+//
+// Tracing is best effort: errors while writing the trace are ignored.
+#[derive(Debug)]
+pub struct TraceWriter<T: Write> {
+    out: T,
+}
+
+impl<T: Write> TraceWriter<T> {
+    pub fn new(out: T) -> Self {
+        Self { out }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.out
+    }
+}
+
+impl<W: Word, T: Write> Observer<W> for TraceWriter<T> {
+    fn instruction(&mut self, pc: Address, instruction: &Instruction<W>, params: &[Option<W>]) {
+        let _ = if params.is_empty() {
+            writeln!(self.out, "{:>5}: {}", pc, instruction)
+        } else {
+            let params = params
+                .iter()
+                .map(|w| w.as_ref().map_or("?".to_string(), W::to_string))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            writeln!(
+                self.out,
+                "{:>5}: {:<24} ; {}",
+                pc,
+                instruction.to_string(),
+                params
+            )
+        };
+    }
+
+    fn write(&mut self, address: Address, value: &W) {
+        let _ = writeln!(self.out, "{:>10} <- {}", format!("[{}]", address), value);
+    }
+}
+
+// Something observed while the VM was running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent<W: Word> {
+    Instruction {
+        pc: Address,
+        instruction: Instruction<W>,
+        params: Vec<Option<W>>,
+    },
+    Write {
+        address: Address,
+        value: W,
+    },
+}
+
+// Records the most recent events, up to its capacity; older events are dropped.
+#[derive(Debug, Clone)]
+pub struct Recorder<W: Word> {
+    events: VecDeque<TraceEvent<W>>,
+    capacity: usize,
+}
+
+impl<W: Word> Recorder<W> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    // The recorded events, oldest first.
+    pub fn events(&self) -> impl Iterator<Item = &TraceEvent<W>> {
+        self.events.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    fn record(&mut self, event: TraceEvent<W>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+
+        self.events.push_back(event);
+    }
+}
+
+impl<W: Word> Observer<W> for Recorder<W> {
+    fn instruction(&mut self, pc: Address, instruction: &Instruction<W>, params: &[Option<W>]) {
+        self.record(TraceEvent::Instruction {
+            pc,
+            instruction: instruction.clone(),
+            params: params.to_vec(),
+        });
+    }
+
+    fn write(&mut self, address: Address, value: &W) {
+        self.record(TraceEvent::Write {
+            address,
+            value: value.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{assemble, ExecutionOption, InParam, Memory, Outcome, VmError, VM};
+    use parameterized::parameterized as pm;
+
+    ide!();
+
+    fn program() -> Vec<i64> {
+        assemble(
+            "
+                in [9]
+                add [9], #3, [9]
+                out [9]
+                hlt
+                data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn trace_writer() {
+        let trace = Arc::new(Mutex::new(TraceWriter::new(Vec::new())));
+        let mut vm = VM::with_inputs(program(), vec![5]).with_observer(trace.clone());
+
+        assert_eq!(
+            vm.execute(ExecutionOption::OutputByTapeOutput),
+            Ok(Outcome::Word(8))
+        );
+        drop(vm);
+
+        let trace = Arc::try_unwrap(trace).unwrap().into_inner().unwrap();
+        let trace = String::from_utf8(trace.into_inner()).unwrap();
+
+        assert_eq!(
+            trace,
+            [
+                "    0: in [9]",
+                "       [9] <- 5",
+                "    2: add [9], #3, [9]         ; 5, 3",
+                "       [9] <- 8",
+                "    6: out [9]                  ; 8",
+                "    8: hlt",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    // the targets of the jumps can't be read, but the jumps aren't taken
+    #[pm(program = {
+        vec![2105, 0, -5, 99],
+        vec![6, 4, 9_000_000_000_000, 99, 1],
+    })]
+    fn observing_does_not_change_the_outcome(program: Vec<i64>) {
+        let plain = VM::new(program.clone()).execute(ExecutionOption::OutputByTapeOutput);
+
+        let recorder = Arc::new(Mutex::new(Recorder::new(10)));
+        let observed = VM::new(program)
+            .with_observer(recorder.clone())
+            .execute(ExecutionOption::OutputByTapeOutput);
+
+        assert_eq!(observed, plain);
+        assert!(matches!(
+            recorder.lock().unwrap().events().next(),
+            Some(TraceEvent::Instruction { params, .. }) if params[1].is_none()
+        ));
+    }
+
+    #[test]
+    fn trace_writer_marks_unreadable_params() {
+        let mut trace = TraceWriter::new(Vec::new());
+        Observer::<i64>::instruction(
+            &mut trace,
+            0,
+            &Instruction::JumpIfTrue([InParam::Immediate(0), InParam::Relative(-5)]),
+            &[Some(0), None],
+        );
+
+        assert_eq!(
+            String::from_utf8(trace.into_inner()).unwrap(),
+            "    0: jt #0, rb-5              ; 0, ?\n"
+        );
+    }
+
+    #[test]
+    fn failed_writes_are_not_recorded() {
+        let recorder = Arc::new(Mutex::new(Recorder::new(10)));
+        let memory = Memory::new(vec![1101, 1, 1, 100, 99]).with_limit(50);
+        let mut vm = VM::new(memory).with_observer(recorder.clone());
+
+        assert_eq!(
+            vm.execute(ExecutionOption::default()),
+            Err(VmError::OutOfBounds {
+                pc: 0,
+                address: 100
+            })
+        );
+        assert!(recorder
+            .lock()
+            .unwrap()
+            .events()
+            .all(|event| !matches!(event, TraceEvent::Write { .. })));
+    }
+
+    #[pm(capacity = { 0, 1, 3, 100 }, expected = { 0, 1, 3, 6 })]
+    fn recorder_is_bounded(capacity: usize, expected: usize) {
+        let recorder = Arc::new(Mutex::new(Recorder::new(capacity)));
+        let mut vm = VM::with_inputs(program(), vec![5]).with_observer(recorder.clone());
+        vm.execute(ExecutionOption::OutputByTapeOutput).unwrap();
+
+        assert_eq!(recorder.lock().unwrap().len(), expected);
+    }
+
+    #[test]
+    fn recorder_keeps_latest() {
+        let recorder = Arc::new(Mutex::new(Recorder::new(3)));
+        let mut vm = VM::with_inputs(program(), vec![5]).with_observer(recorder.clone());
+        vm.execute(ExecutionOption::OutputByTapeOutput).unwrap();
+
+        let recorder = recorder.lock().unwrap();
+        let events = recorder.events().collect::<Vec<_>>();
+
+        assert_eq!(
+            events[0],
+            &TraceEvent::Write {
+                address: 9,
+                value: 8
+            }
+        );
+        assert!(matches!(
+            events[1],
+            TraceEvent::Instruction { pc: 6, params, .. } if params == &[Some(8)]
+        ));
+        assert!(matches!(
+            events[2],
+            TraceEvent::Instruction {
+                pc: 8,
+                instruction: Instruction::Ret,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,36 @@
+use num_bigint::BigInt;
+use num_traits::{CheckedAdd, CheckedMul, FromPrimitive, One, ToPrimitive, Zero};
+use std::fmt::{Debug, Display};
+use std::hash::Hash;
+use std::str::FromStr;
+
+// The minimal accessible unit. From day 5 it should support negative numbers which
+// introduces some complications, by needing to translate between a word value and an address.
+//
+// Later programs multiply large constants, so the width is left to the user of the VM.
+// Arithmetic on words is checked: overflowing a word stops the VM instead of wrapping.
+pub trait Word:
+    Clone
+    + Debug
+    + Display
+    + Ord
+    + Hash
+    + Zero
+    + One
+    + CheckedAdd
+    + CheckedMul
+    + ToPrimitive
+    + FromPrimitive
+    + FromStr
+    + Send
+    + Sync
+    + 'static
+{
+}
+
+impl Word for i32 {}
+impl Word for i64 {}
+impl Word for i128 {}
+
+// arbitrary width, can't overflow
+impl Word for BigInt {}